@@ -0,0 +1,61 @@
+//! Generating passwords with the library instead of the command line.
+//!
+//! Run with `cargo run --example generate`. The example checks the passwords
+//! it prints, so it fails if the library breaks its promises, and `cargo
+//! test` runs it as well.
+
+use clap::Parser;
+use gen_pw::{graphemes::grapheme_len, Config};
+use rand::SeedableRng;
+
+fn generate(args: &[&str], count: usize) -> Vec<String> {
+    // The options are the ones of the command line
+    let mut config = Config::parse_from(std::iter::once("gen-pw").chain(args.iter().copied()));
+    config.normalize().unwrap();
+    gen_pw::validate(&config).unwrap();
+    let required = gen_pw::required_classes(&config);
+
+    // A seeded generator makes the output reproducible
+    let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(42);
+    let passwords = gen_pw::passwords(&config, &required, &mut rng)
+        .unwrap()
+        .take(count)
+        .map(|generated| generated.unwrap().password)
+        .collect::<Vec<_>>();
+    for password in &passwords {
+        let len = grapheme_len(password);
+        assert!(config.min <= len && len <= config.max, "{password}");
+    }
+    passwords
+}
+
+fn main() {
+    println!("chars:");
+    for password in generate(&["--min", "12", "--max", "16", "chars"], 3) {
+        println!("  {password}");
+    }
+
+    // A word list on disk, so this does not depend on aspell
+    let list = std::env::temp_dir().join("gen-pw-example-words.txt");
+    std::fs::write(&list, "correct horse battery staple orange river\n").unwrap();
+    let list = list.to_str().unwrap();
+    println!("dict:");
+    for password in generate(
+        &["--dict-file", list, "--min", "16", "--max", "24", "dict"],
+        3,
+    ) {
+        println!("  {password}");
+    }
+
+    // The same seed and options yield the same passwords
+    assert_eq!(
+        generate(&["chars"], 5),
+        generate(&["chars"], 5),
+        "seeded generation is not reproducible"
+    );
+}
+
+#[test]
+fn runs() {
+    main();
+}
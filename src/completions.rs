@@ -0,0 +1,281 @@
+//! Shell completion scripts for the `completions` command.
+//!
+//! The scripts are generated from the clap definition of the options, so they
+//! stay in sync with them. They complete the subcommands and the option names
+//! of the global options and of the subcommand on the command line, but not
+//! option values.
+
+use std::fmt::Write;
+
+use clap::ValueEnum;
+
+#[derive(Clone, ValueEnum, PartialEq, Copy, Debug)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    #[clap(name = "powershell")]
+    PowerShell,
+}
+
+/// An option as the completions see it.
+struct Flag {
+    long: Option<String>,
+    short: Option<char>,
+    help: String,
+    takes_value: bool,
+}
+
+impl Flag {
+    /// The spellings of the option, e.g. `-h` and `--help`.
+    fn names(&self) -> impl Iterator<Item = String> + '_ {
+        self.short
+            .map(|c| format!("-{c}"))
+            .into_iter()
+            .chain(self.long.iter().map(|l| format!("--{l}")))
+    }
+}
+
+fn flags(cmd: &clap::Command) -> Vec<Flag> {
+    cmd.get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+        .map(|arg| Flag {
+            long: arg.get_long().map(str::to_string),
+            short: arg.get_short(),
+            help: first_line(arg.get_help()),
+            takes_value: arg.is_takes_value_set(),
+        })
+        .collect()
+}
+
+fn first_line(help: Option<&str>) -> String {
+    help.and_then(|h| h.lines().next())
+        .unwrap_or("")
+        .to_string()
+}
+
+/// The completion script of `shell` for `cmd`, installed as `bin`.
+pub fn generate(shell: Shell, mut cmd: clap::Command, bin: &str) -> String {
+    // Adds `--help`, `--version` and the `help` command
+    cmd.build();
+    let cmd = &cmd;
+    let global = flags(cmd);
+    let subcommands = cmd
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set())
+        .map(|sub| (sub.get_name(), first_line(sub.get_about()), flags(sub)))
+        .collect::<Vec<_>>();
+    let words = |flags: &[Flag]| flags.iter().flat_map(Flag::names).collect::<Vec<_>>();
+    let names = subcommands
+        .iter()
+        .map(|(name, _, _)| *name)
+        .collect::<Vec<_>>();
+    let function = format!("_{}", bin.replace('-', "_"));
+    let mut out = String::new();
+    match shell {
+        Shell::Bash => {
+            writeln!(out, "{function}() {{").unwrap();
+            writeln!(
+                out,
+                "    local cur=${{COMP_WORDS[COMP_CWORD]}} cmd= word opts"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "    for word in \"${{COMP_WORDS[@]:1:COMP_CWORD-1}}\"; do"
+            )
+            .unwrap();
+            writeln!(out, "        case $word in").unwrap();
+            writeln!(out, "            {}) cmd=$word ;;", names.join("|")).unwrap();
+            writeln!(out, "        esac").unwrap();
+            writeln!(out, "    done").unwrap();
+            writeln!(out, "    case $cmd in").unwrap();
+            for (name, _, flags) in &subcommands {
+                writeln!(
+                    out,
+                    "        {name}) opts=\"{}\" ;;",
+                    words(flags).join(" ")
+                )
+                .unwrap();
+            }
+            let top = words(&global)
+                .into_iter()
+                .chain(names.iter().map(|n| n.to_string()));
+            writeln!(
+                out,
+                "        *) opts=\"{}\" ;;",
+                top.collect::<Vec<_>>().join(" ")
+            )
+            .unwrap();
+            writeln!(out, "    esac").unwrap();
+            writeln!(out, "    COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))").unwrap();
+            writeln!(out, "}}").unwrap();
+            writeln!(out, "complete -F {function} -o default {bin}").unwrap();
+        }
+        Shell::Zsh => {
+            writeln!(out, "#compdef {bin}").unwrap();
+            writeln!(out).unwrap();
+            writeln!(out, "{function}() {{").unwrap();
+            writeln!(out, "    local cmd word").unwrap();
+            writeln!(out, "    for word in ${{words[2,CURRENT-1]}}; do").unwrap();
+            writeln!(out, "        case $word in").unwrap();
+            writeln!(out, "            ({}) cmd=$word ;;", names.join("|")).unwrap();
+            writeln!(out, "        esac").unwrap();
+            writeln!(out, "    done").unwrap();
+            writeln!(out, "    case $cmd in").unwrap();
+            for (name, _, flags) in &subcommands {
+                writeln!(
+                    out,
+                    "        ({name}) compadd -- {} ;;",
+                    words(flags).join(" ")
+                )
+                .unwrap();
+            }
+            writeln!(out, "        (*)").unwrap();
+            writeln!(out, "            compadd -- {}", words(&global).join(" ")).unwrap();
+            writeln!(out, "            compadd -- {}", names.join(" ")).unwrap();
+            writeln!(out, "            ;;").unwrap();
+            writeln!(out, "    esac").unwrap();
+            writeln!(out, "}}").unwrap();
+            writeln!(out).unwrap();
+            writeln!(out, "compdef {function} {bin}").unwrap();
+        }
+        Shell::Fish => {
+            let fish = |out: &mut String, condition: &str, flag: &Flag| {
+                write!(out, "complete -c {bin} -n '{condition}'").unwrap();
+                if let Some(c) = flag.short {
+                    write!(out, " -s {c}").unwrap();
+                }
+                if let Some(long) = &flag.long {
+                    write!(out, " -l {long}").unwrap();
+                }
+                if flag.takes_value {
+                    write!(out, " -r").unwrap();
+                }
+                writeln!(out, " -d {}", fish_quote(&flag.help)).unwrap();
+            };
+            for flag in &global {
+                fish(&mut out, "__fish_use_subcommand", flag);
+            }
+            for (name, about, _) in &subcommands {
+                writeln!(
+                    out,
+                    "complete -c {bin} -n '__fish_use_subcommand' -f -a {name} -d {}",
+                    fish_quote(about)
+                )
+                .unwrap();
+            }
+            for (name, _, flags) in &subcommands {
+                for flag in flags {
+                    fish(
+                        &mut out,
+                        &format!("__fish_seen_subcommand_from {name}"),
+                        flag,
+                    );
+                }
+            }
+        }
+        Shell::PowerShell => {
+            let list = |words: Vec<String>| {
+                words
+                    .iter()
+                    .map(|w| format!("'{w}'"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            let names = names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+            writeln!(
+                out,
+                "Register-ArgumentCompleter -Native -CommandName '{bin}' -ScriptBlock {{"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "    param($wordToComplete, $commandAst, $cursorPosition)"
+            )
+            .unwrap();
+            writeln!(out, "    $commands = @({})", list(names.clone())).unwrap();
+            writeln!(out, "    $command = ''").unwrap();
+            writeln!(
+                out,
+                "    foreach ($element in $commandAst.CommandElements | Select-Object -Skip 1) {{"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "        if ($element.Extent.StartOffset -ge $cursorPosition) {{ break }}"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "        if ($commands -contains $element.ToString()) {{ $command = $element.ToString() }}"
+            )
+            .unwrap();
+            writeln!(out, "    }}").unwrap();
+            writeln!(out, "    $candidates = switch ($command) {{").unwrap();
+            for (name, _, flags) in &subcommands {
+                writeln!(out, "        '{name}' {{ @({}) }}", list(words(flags))).unwrap();
+            }
+            let top = words(&global).into_iter().chain(names).collect();
+            writeln!(out, "        default {{ @({}) }}", list(top)).unwrap();
+            writeln!(out, "    }}").unwrap();
+            writeln!(
+                out,
+                "    $candidates | Where-Object {{ $_ -like \"$wordToComplete*\" }} | ForEach-Object {{"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterName', $_)"
+            )
+            .unwrap();
+            writeln!(out, "    }}").unwrap();
+            writeln!(out, "}}").unwrap();
+        }
+    }
+    out
+}
+
+/// Quote `s` as a single fish word.
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn every_shell_completes_commands_and_flags() {
+        let cmd = crate::Opts::command();
+        let subcommands = cmd
+            .get_subcommands()
+            .filter(|sub| !sub.is_hide_set())
+            .map(|sub| sub.get_name().to_string())
+            .collect::<Vec<_>>();
+        assert!(subcommands.iter().any(|name| name == "dict"));
+        for shell in Shell::value_variants() {
+            let script = generate(*shell, cmd.clone(), "gen-pw");
+            for name in &subcommands {
+                assert!(script.contains(name.as_str()), "{shell:?} lacks {name}");
+            }
+            for flag in ["min", "count", "symbols"] {
+                let spelled = match shell {
+                    Shell::Fish => format!("-l {flag} "),
+                    Shell::PowerShell => format!("'--{flag}'"),
+                    _ => format!("--{flag} "),
+                };
+                assert!(script.contains(&spelled), "{shell:?} lacks {spelled}");
+            }
+        }
+    }
+
+    #[test]
+    fn fish_quote_escapes() {
+        assert_eq!(fish_quote("plain"), "'plain'");
+        assert_eq!(fish_quote("don't"), "'don\\'t'");
+        assert_eq!(fish_quote("a\\b"), "'a\\\\b'");
+    }
+}
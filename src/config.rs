@@ -0,0 +1,590 @@
+//! Loading of options from a TOML configuration file.
+//!
+//! Only the subset of TOML needed to describe command line options is
+//! supported: tables, strings, numbers, booleans and arrays. Rather than
+//! mapping the file onto `Opts` directly, the values are turned back into
+//! command line arguments and spliced in front of the ones the user passed.
+//! That way clap performs all of its usual parsing and validation and flags
+//! given on the command line always override the values from the file.
+
+use std::{ffi::OsString, path::Path};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    /// Integers and floats are kept verbatim and parsed by clap later
+    Number(String),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Key-value pairs before the first table header
+    pub global: Vec<(String, Value)>,
+    /// `[name]` tables, in order of appearance
+    pub tables: Vec<(String, Vec<(String, Value)>)>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let src = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config file {}: {e}", path.display()))?;
+        Self::parse(&src).map_err(|e| format!("In config file {}: {e}", path.display()))
+    }
+
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut config = Config::default();
+        for (lineno, line) in src.lines().enumerate() {
+            let mut cursor = Cursor::new(line);
+            let at_line = |e: String| format!("line {}: {e}", lineno + 1);
+            cursor.skip_whitespace();
+            if cursor.at_end() {
+                continue;
+            }
+            if cursor.eat('[') {
+                let name = cursor.key().map_err(at_line)?;
+                if !cursor.eat(']') {
+                    return Err(at_line("expected ']' after table name".to_string()));
+                }
+                cursor.expect_end().map_err(at_line)?;
+                config.tables.push((name, vec![]));
+                continue;
+            }
+            let key = cursor.key().map_err(at_line)?;
+            if !cursor.eat('=') {
+                return Err(at_line(format!("expected '=' after key '{key}'")));
+            }
+            let value = cursor.value().map_err(at_line)?;
+            cursor.expect_end().map_err(at_line)?;
+            match config.tables.last_mut() {
+                Some((_, entries)) => entries.push((key, value)),
+                None => config.global.push((key, value)),
+            }
+        }
+        Ok(config)
+    }
+
+    fn table(&self, name: &str) -> &[(String, Value)] {
+        self.tables
+            .iter()
+            .find(|(table, _)| table == name)
+            .map_or(&[], |(_, entries)| entries.as_slice())
+    }
+}
+
+struct Cursor<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(line: &'a str) -> Self {
+        Cursor { rest: line }
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start();
+        if self.rest.starts_with('#') {
+            self.rest = "";
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.rest.is_empty()
+    }
+
+    fn expect_end(&mut self) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.at_end() {
+            Ok(())
+        } else {
+            Err(format!("unexpected trailing input '{}'", self.rest))
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        if self.rest.starts_with('"') || self.rest.starts_with('\'') {
+            return self.string();
+        }
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(self.rest.len());
+        if end == 0 {
+            return Err("expected a key".to_string());
+        }
+        let (key, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Ok(key.to_string())
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        if self.rest.starts_with('"') || self.rest.starts_with('\'') {
+            return self.string().map(Value::String);
+        }
+        if self.eat('[') {
+            let mut items = vec![];
+            loop {
+                if self.eat(']') {
+                    return Ok(Value::Array(items));
+                }
+                items.push(self.value()?);
+                if !self.eat(',') {
+                    return if self.eat(']') {
+                        Ok(Value::Array(items))
+                    } else {
+                        Err("expected ',' or ']' in array".to_string())
+                    };
+                }
+            }
+        }
+        let end = self
+            .rest
+            .find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#')
+            .unwrap_or(self.rest.len());
+        let (word, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        match word {
+            "true" => Ok(Value::Boolean(true)),
+            "false" => Ok(Value::Boolean(false)),
+            n if !n.is_empty()
+                && n.chars()
+                    .all(|c| c.is_ascii_digit() || "+-._eE".contains(c)) =>
+            {
+                Ok(Value::Number(n.replace('_', "")))
+            }
+            "" => Err("expected a value".to_string()),
+            other => Err(format!("invalid value '{other}'")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let mut chars = self.rest.char_indices();
+        let quote = chars.next().map(|(_, c)| c);
+        let literal = quote == Some('\'');
+        let mut out = String::new();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                c if Some(c) == quote => {
+                    self.rest = &self.rest[i + c.len_utf8()..];
+                    return Ok(out);
+                }
+                '\\' if !literal => match chars.next().map(|(_, c)| c) {
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('r') => out.push('\r'),
+                    Some('u') => {
+                        let hex = chars.by_ref().take(4).map(|(_, c)| c).collect::<String>();
+                        out.push(
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| format!("invalid unicode escape '\\u{hex}'"))?,
+                        );
+                    }
+                    Some(other) => return Err(format!("invalid escape sequence '\\{other}'")),
+                    None => break,
+                },
+                c => out.push(c),
+            }
+        }
+        Err("unterminated string".to_string())
+    }
+}
+
+/// The option of `cmd` with the long name or alias `name`.
+fn find_long<'c, 'h>(cmd: &'c clap::Command<'h>, name: &str) -> Option<&'c clap::Arg<'h>> {
+    cmd.get_arguments().find(|a| {
+        a.get_long() == Some(name) || a.get_all_aliases().is_some_and(|all| all.contains(&name))
+    })
+}
+
+/// The option of `cmd` with the short flag or short alias `c`.
+fn find_short<'c, 'h>(cmd: &'c clap::Command<'h>, c: char) -> Option<&'c clap::Arg<'h>> {
+    cmd.get_arguments().find(|a| {
+        a.get_short() == Some(c)
+            || a.get_all_short_aliases()
+                .is_some_and(|all| all.contains(&c))
+    })
+}
+
+/// The options of `cmd` spelled by the command line argument `arg`, several
+/// for a cluster of short flags like `-vq`, and whether the next argument is
+/// the value of the last of them.
+fn options_in<'c, 'h>(cmd: &'c clap::Command<'h>, arg: &str) -> (Vec<&'c clap::Arg<'h>>, bool) {
+    if let Some(long) = arg.strip_prefix("--") {
+        let (name, value) = match long.split_once('=') {
+            Some((name, _)) => (name, true),
+            None => (long, false),
+        };
+        return match find_long(cmd, name) {
+            Some(a) => (vec![a], !value && a.is_takes_value_set()),
+            None => (vec![], false),
+        };
+    }
+    let Some(shorts) = arg.strip_prefix('-') else {
+        return (vec![], false);
+    };
+    let mut found = vec![];
+    let mut chars = shorts.chars();
+    while let Some(a) = chars.next().and_then(|c| find_short(cmd, c)) {
+        found.push(a);
+        if a.is_takes_value_set() {
+            // The rest of the cluster is the value, if there is any
+            return (found, chars.as_str().is_empty());
+        }
+    }
+    (found, false)
+}
+
+/// The flag for the option `key` of a config table, the long name or alias
+/// of an option of `cmd` with `_` or `-` between words, or a short flag.
+fn config_flag<'c, 'h>(
+    cmd: &'c clap::Command<'h>,
+    key: &str,
+) -> (String, Option<&'c clap::Arg<'h>>) {
+    let name = key.replace('_', "-");
+    let mut chars = key.chars();
+    let arg = find_long(cmd, &name)
+        .or_else(|| cmd.get_arguments().find(|a| a.get_id() == key))
+        .or_else(|| match (chars.next(), chars.next()) {
+            (Some(c), None) => find_short(cmd, c),
+            _ => None,
+        });
+    let flag = match arg {
+        Some(a) => match (a.get_long(), a.get_short()) {
+            (Some(long), _) => format!("--{long}"),
+            (None, Some(short)) => format!("-{short}"),
+            (None, None) => format!("--{name}"),
+        },
+        // Unknown to clap, which reports it
+        None => format!("--{name}"),
+    };
+    (flag, arg)
+}
+
+/// Whether `cmd` refuses to combine the options `a` and `b`.
+fn conflict(cmd: &clap::Command, a: &clap::Arg, b: &clap::Arg) -> bool {
+    let lists = |a: &clap::Arg, b: &clap::Arg| {
+        cmd.get_arg_conflicts_with(a)
+            .iter()
+            .any(|c| c.get_id() == b.get_id())
+    };
+    lists(a, b) || lists(b, a)
+}
+
+/// Flags for the entries in a config table for `cmd`, skipping the ones the
+/// user already passed on the command line and the ones that conflict with
+/// them.
+fn entries_to_args(
+    entries: &[(String, Value)],
+    user_args: &[OsString],
+    cmd: &clap::Command,
+) -> Vec<OsString> {
+    let mut given = vec![];
+    let mut i = 0;
+    while i < user_args.len() {
+        let arg = user_args[i].to_string_lossy();
+        if arg == "--" {
+            break;
+        }
+        let (options, value_next) = options_in(cmd, &arg);
+        given.extend(options);
+        i += 1 + value_next as usize;
+    }
+    let mut out = vec![];
+    for (key, value) in entries {
+        let (flag, arg) = config_flag(cmd, key);
+        let overridden = arg.is_some_and(|a| {
+            given
+                .iter()
+                .any(|g| g.get_id() == a.get_id() || conflict(cmd, a, g))
+        });
+        if overridden {
+            continue;
+        }
+        push_value(&flag, value, &mut out);
+    }
+    out
+}
+
+fn push_value(flag: &str, value: &Value, out: &mut Vec<OsString>) {
+    match value {
+        Value::Boolean(true) => out.push(flag.into()),
+        Value::Boolean(false) => (),
+        // In one argument, so clap does not take a value like `-_!` for a flag
+        Value::String(s) | Value::Number(s) => out.push(format!("{flag}={s}").into()),
+        Value::Array(items) => items.iter().for_each(|v| push_value(flag, v, out)),
+    }
+}
+
+/// Index of the subcommand in `args` (which excludes the binary name), found
+/// by skipping over options and their values.
+fn subcommand_position(args: &[OsString], cmd: &clap::Command) -> Option<usize> {
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].to_string_lossy();
+        if !arg.starts_with('-') {
+            return Some(i);
+        }
+        let (_, value_next) = options_in(cmd, &arg);
+        i += 1 + value_next as usize;
+    }
+    None
+}
+
+/// The options before the subcommand in `args` (which start with the binary
+/// name) with `--from-config <path>` in front, for evaluating the same
+/// options against several configuration files with [`merge_args`].
+pub fn with_config(args: &[OsString], path: &Path, cmd: &clap::Command) -> Vec<OsString> {
+    let mut cmd = cmd.clone();
+    cmd.build();
+    let (bin, user_args) = match args.split_first() {
+        Some((bin, rest)) => (bin.clone(), rest),
+        None => ("gen-pw".into(), args),
+    };
+    let global_args = &user_args[..subcommand_position(user_args, &cmd).unwrap_or(user_args.len())];
+    let mut out = vec![bin, "--from-config".into(), path.into()];
+    out.extend_from_slice(global_args);
+    out
+}
+
+/// If `args` contain `--from-config <path>`, load the file and merge its
+/// options into the arguments. Options given in `args` take precedence.
+pub fn merge_args(args: Vec<OsString>, cmd: &clap::Command) -> Result<Vec<OsString>, String> {
+    let mut cmd = cmd.clone();
+    cmd.build();
+    let (bin, user_args) = match args.split_first() {
+        Some((bin, rest)) => (bin.clone(), rest.to_vec()),
+        None => return Ok(args),
+    };
+    let subcommand_at = subcommand_position(&user_args, &cmd);
+    let global_args = &user_args[..subcommand_at.unwrap_or(user_args.len())];
+    let path = global_args.iter().enumerate().find_map(|(i, a)| {
+        let a = a.to_str()?;
+        if a == "--from-config" {
+            global_args.get(i + 1).cloned()
+        } else {
+            a.strip_prefix("--from-config=").map(OsString::from)
+        }
+    });
+    let Some(path) = path else {
+        return Ok(args);
+    };
+    let config = Config::load(Path::new(&path))?;
+
+    let (command, mut global) = config
+        .global
+        .iter()
+        .cloned()
+        .partition::<Vec<_>, _>(|(key, _)| key == "command");
+    global.retain(|(key, _)| key != "from_config" && key != "from-config");
+
+    let mut merged = vec![bin];
+    merged.extend(entries_to_args(&global, global_args, &cmd));
+    match subcommand_at {
+        Some(at) => {
+            let name = user_args[at].to_string_lossy().into_owned();
+            let sub_args = &user_args[at + 1..];
+            merged.extend_from_slice(&user_args[..=at]);
+            if let Some(sub) = cmd.find_subcommand(&name) {
+                merged.extend(entries_to_args(config.table(&name), sub_args, sub));
+            }
+            merged.extend_from_slice(sub_args);
+        }
+        None => {
+            merged.extend_from_slice(&user_args);
+            match command.last() {
+                Some((_, Value::String(name))) => {
+                    merged.push(name.into());
+                    if let Some(sub) = cmd.find_subcommand(name) {
+                        merged.extend(entries_to_args(config.table(name), &[], sub));
+                    }
+                }
+                Some(_) => return Err("'command' must be a string".to_string()),
+                None => (),
+            }
+        }
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, Parser};
+
+    use super::*;
+
+    fn command() -> clap::Command<'static> {
+        let mut cmd = crate::Opts::command();
+        cmd.build();
+        cmd
+    }
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    fn entries(src: &str) -> Vec<(String, Value)> {
+        Config::parse(src).unwrap().global
+    }
+
+    #[test]
+    fn finds_the_subcommand_after_option_values() {
+        let cmd = command();
+        let cases: &[&[&str]] = &[
+            &["chars"],
+            &["--symbols", "!@", "chars"],
+            &["--symbol-chars", "!@", "chars"],
+            &["--symbol-chars=!@", "--min", "5", "chars"],
+            &["-v", "--max=30", "chars"],
+            &["-vq", "--tries", "3", "chars"],
+        ];
+        for case in cases {
+            assert_eq!(
+                subcommand_position(&args(case), &cmd),
+                Some(case.len() - 1),
+                "{case:?}"
+            );
+        }
+        assert_eq!(subcommand_position(&args(&["--min", "5"]), &cmd), None);
+    }
+
+    #[test]
+    fn short_aliases_take_values() {
+        let mut cmd = clap::Command::new("test")
+            .arg(
+                clap::Arg::new("length")
+                    .long("length")
+                    .short('l')
+                    .short_alias('n')
+                    .takes_value(true),
+            )
+            .subcommand(clap::Command::new("run"));
+        cmd.build();
+        for case in [
+            &["-l", "5", "run"][..],
+            &["-n", "5", "run"],
+            &["-n5", "run"],
+        ] {
+            assert_eq!(
+                subcommand_position(&args(case), &cmd),
+                Some(case.len() - 1),
+                "{case:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn config_keys_are_options_of_the_command() {
+        let cmd = command();
+        assert_eq!(
+            entries_to_args(&entries("symbol_chars = '!@'\nv = true\n"), &[], &cmd),
+            args(&["--symbols=!@", "--verbose"])
+        );
+        // Unknown keys are left to clap to report
+        assert_eq!(
+            entries_to_args(&entries("no_such_option = 1\n"), &[], &cmd),
+            args(&["--no-such-option=1"])
+        );
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let cmd = command();
+        let cases: &[(&str, &[&str])] = &[
+            ("verbose = true", &["-v"]),
+            ("verbose = true", &["--verbose"]),
+            ("min = 10", &["--min=12"]),
+            ("symbols = '!@'", &["--symbol-chars", "#"]),
+            ("symbol-chars = '!@'", &["--symbols=#"]),
+            // Conflicting options
+            ("quiet = true", &["-v"]),
+            ("min = 10", &["--target-length", "20"]),
+            ("target_length = 20", &["--max", "30"]),
+        ];
+        for (config, user) in cases {
+            assert_eq!(
+                entries_to_args(&entries(config), &args(user), &cmd),
+                Vec::<OsString>::new(),
+                "{config} with {user:?}"
+            );
+        }
+        assert_eq!(
+            entries_to_args(&entries("max = 30"), &args(&["--min", "12"]), &cmd),
+            args(&["--max=30"])
+        );
+    }
+
+    #[test]
+    fn merged_arguments_parse() {
+        let path = std::env::temp_dir().join("gen-pw-test-merge.toml");
+        std::fs::write(&path, "verbose = true\nmin = 10\ncommand = 'chars'\n").unwrap();
+        let user = args(&[
+            "gen-pw",
+            "--from-config",
+            path.to_str().unwrap(),
+            "-v",
+            "--target-length",
+            "16",
+        ]);
+        let merged = merge_args(user, &command()).unwrap();
+        let opts = crate::Opts::try_parse_from(merged).unwrap();
+        assert!(opts.verbose);
+        assert_eq!(opts.target_length, Some(16));
+    }
+
+    #[test]
+    fn config_matches_the_command_line() {
+        let path = std::env::temp_dir().join("gen-pw-test-equivalent.toml");
+        std::fs::write(
+            &path,
+            "symbols = '-_!'\nmin = 12\nrequire = ['number', 'symbol']\nverbose = true\n\
+             command = 'dict'\n[dict]\nmin-words = 2\n",
+        )
+        .unwrap();
+        let from_config = merge_args(
+            args(&["gen-pw", "--from-config", path.to_str().unwrap()]),
+            &command(),
+        )
+        .unwrap();
+        let from_config = crate::Opts::try_parse_from(from_config).unwrap();
+        let direct = crate::Opts::try_parse_from([
+            "gen-pw",
+            "--symbols=-_!",
+            "--min",
+            "12",
+            "--require",
+            "number",
+            "--require",
+            "symbol",
+            "-v",
+            "dict",
+            "--min-words",
+            "2",
+        ])
+        .unwrap();
+        assert_eq!(from_config.symbols, "-_!");
+        assert_eq!(from_config.symbols, direct.symbols);
+        assert_eq!(from_config.min, direct.min);
+        assert_eq!(from_config.require, direct.require);
+        assert_eq!(from_config.verbose, direct.verbose);
+        assert_eq!(from_config.command.word_band(), direct.command.word_band());
+        assert_eq!(from_config.from_config.as_deref(), Some(path.as_path()));
+    }
+}
@@ -0,0 +1,50 @@
+//! External programs that accept or reject candidates, see `--post-filter`.
+//!
+//! The candidate is passed on stdin, followed by a newline, so it never shows
+//! up in the process list. An exit code of zero accepts it, any other code
+//! rejects it.
+
+use std::{
+    io::{ErrorKind, Write},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/// Whether `program` can be run, looking up bare names in the `PATH` like
+/// [`Command`] does.
+pub fn exists(program: &Path) -> bool {
+    if program.components().count() > 1 {
+        return program.is_file();
+    }
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+/// Whether `program` accepts `candidate`.
+pub fn accepts(program: &Path, candidate: &str) -> Result<bool, String> {
+    let mut child = Command::new(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Could not run the post filter {}: {e}", program.display()))?;
+    let written = child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(format!("{candidate}\n").as_bytes());
+    match written {
+        // The filter decided without reading the candidate
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => {}
+        Err(e) => {
+            return Err(format!(
+                "Could not pass the candidate to the post filter {}: {e}",
+                program.display()
+            ))
+        }
+        Ok(()) => {}
+    }
+    let status = child
+        .wait()
+        .map_err(|e| format!("Could not run the post filter {}: {e}", program.display()))?;
+    Ok(status.success())
+}
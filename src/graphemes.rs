@@ -0,0 +1,160 @@
+//! Approximate segmentation of strings into user perceived characters.
+//!
+//! This is an approximation of the extended grapheme clusters of Unicode
+//! Standard Annex #29, not a full implementation. It covers the parts of the
+//! rules that matter for passwords: combining marks, variation selectors,
+//! emoji modifiers, zero width joiner sequences, tag sequences, pairs of
+//! regional indicators (flags) and Devanagari vowel signs and conjuncts. A ZWJ
+//! family emoji, a flag or a conjunct like `क्ष` therefore counts as a single
+//! character towards the password length. Other scripts with spacing marks or
+//! conjuncts, and Hangul syllables written as separate jamo, are split into
+//! more clusters than the annex defines.
+//!
+//! Without the `graphemes` feature every `char` is a cluster of its own, so
+//! lengths are counted in Unicode scalar values.
+
+const ZWJ: char = '\u{200D}';
+/// Joins the consonants around it into a conjunct
+const DEVANAGARI_VIRAMA: char = '\u{094D}';
+
+fn is_devanagari_consonant(c: char) -> bool {
+    matches!(c, '\u{0915}'..='\u{0939}' | '\u{0958}'..='\u{095F}' | '\u{0978}'..='\u{097F}')
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+/// Characters that never start a cluster but attach to the previous one.
+fn is_extender(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036F}' // combining diacritical marks
+        | '\u{1AB0}'..='\u{1AFF}'
+        | '\u{1DC0}'..='\u{1DFF}'
+        | '\u{20D0}'..='\u{20FF}' // combining marks for symbols, incl. keycap
+        | '\u{FE00}'..='\u{FE0F}' // variation selectors
+        | '\u{FE20}'..='\u{FE2F}'
+        | '\u{1F3FB}'..='\u{1F3FF}' // emoji skin tone modifiers
+        | '\u{E0020}'..='\u{E007F}' // tags
+        | '\u{E0100}'..='\u{E01EF}'
+        // Devanagari signs, the spacing ones included, and the virama
+        | '\u{0900}'..='\u{0903}'
+        | '\u{093A}'..='\u{093C}'
+        | '\u{093E}'..='\u{094F}'
+        | '\u{0951}'..='\u{0957}'
+        | '\u{0962}'..='\u{0963}'
+        | ZWJ
+    )
+}
+
+/// Iterate over the grapheme clusters of `s`.
+pub fn graphemes(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let mut chars = rest.char_indices().peekable();
+        let (_, first) = chars.next()?;
+        let mut prev = first;
+        let mut regional_indicators = usize::from(is_regional_indicator(first));
+        let mut end = rest.len();
+        while let Some(&(i, c)) = chars.peek() {
+            let joins = cfg!(feature = "graphemes")
+                && (is_extender(c)
+                    || prev == ZWJ
+                    || (prev == DEVANAGARI_VIRAMA && is_devanagari_consonant(c))
+                    || (prev == '\r' && c == '\n')
+                    || (is_regional_indicator(c) && regional_indicators % 2 == 1));
+            if !joins {
+                end = i;
+                break;
+            }
+            if is_regional_indicator(c) {
+                regional_indicators += 1;
+            }
+            prev = c;
+            chars.next();
+        }
+        let (cluster, remainder) = rest.split_at(end);
+        rest = remainder;
+        Some(cluster)
+    })
+}
+
+/// Length of `s` in grapheme clusters.
+pub fn grapheme_len(s: &str) -> usize {
+    graphemes(s).count()
+}
+
+/// The longest prefix of `s` with at most `max` grapheme clusters, so a
+/// cut never separates a character from its combining marks or splits an
+/// emoji sequence.
+pub fn truncate_graphemes(s: &str, max: usize) -> &str {
+    let end = graphemes(s).take(max).map(str::len).sum();
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clusters(s: &str) -> Vec<&str> {
+        graphemes(s).collect()
+    }
+
+    #[cfg(not(feature = "graphemes"))]
+    #[test]
+    fn characters_without_the_feature() {
+        assert_eq!(clusters("e\u{301}x"), ["e", "\u{301}", "x"]);
+        assert_eq!(grapheme_len("🇩🇪"), 2);
+    }
+
+    #[cfg(feature = "graphemes")]
+    #[test]
+    fn combining_marks_attach_to_their_base() {
+        assert_eq!(clusters("e\u{301}x"), ["e\u{301}", "x"]);
+        assert_eq!(grapheme_len("a\u{308}\u{301}"), 1);
+    }
+
+    #[cfg(feature = "graphemes")]
+    #[test]
+    fn emoji_sequences_are_one_cluster() {
+        let family = "👩\u{200D}👩\u{200D}👧";
+        assert_eq!(clusters(&format!("{family}a")), [family, "a"]);
+        assert_eq!(clusters("👍🏽!"), ["👍🏽", "!"]);
+        assert_eq!(clusters("1\u{FE0F}\u{20E3}"), ["1\u{FE0F}\u{20E3}"]);
+    }
+
+    #[cfg(feature = "graphemes")]
+    #[test]
+    fn regional_indicators_pair_up() {
+        assert_eq!(clusters("🇩🇪"), ["🇩🇪"]);
+        // A third indicator starts the next flag
+        assert_eq!(clusters("🇩🇪🇫🇷🇮"), ["🇩🇪", "🇫🇷", "🇮"]);
+    }
+
+    #[cfg(feature = "graphemes")]
+    #[test]
+    fn devanagari_conjuncts_and_signs() {
+        assert_eq!(clusters("क्ष"), ["क्ष"]);
+        assert_eq!(clusters("क्षि"), ["क्षि"]);
+        // Vowel signs, spacing or not, and the anusvara attach
+        assert_eq!(clusters("हिंदी"), ["हिं", "दी"]);
+        assert_eq!(grapheme_len("नमस्ते"), 3);
+    }
+
+    #[cfg(feature = "graphemes")]
+    #[test]
+    fn truncation_keeps_clusters_whole() {
+        assert_eq!(truncate_graphemes("e\u{301}e\u{301}", 1), "e\u{301}");
+        let family = "👩\u{200D}👩\u{200D}👧";
+        assert_eq!(truncate_graphemes(&format!("{family}{family}"), 1), family);
+        assert_eq!(truncate_graphemes("क्षa", 1), "क्ष");
+        assert_eq!(truncate_graphemes("abc", 10), "abc");
+        assert_eq!(truncate_graphemes("abc", 0), "");
+    }
+
+    #[cfg(feature = "graphemes")]
+    #[test]
+    fn crlf_is_one_cluster() {
+        assert_eq!(clusters("a\r\nb"), ["a", "\r\n", "b"]);
+    }
+}
@@ -0,0 +1,30 @@
+//! Notice Ctrl-C during long batch runs so already generated passwords can be
+//! flushed before exiting.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(all(unix, feature = "sigint"))]
+extern "C" fn on_sigint(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Replace the default SIGINT behavior (immediate termination) with setting a
+/// flag that the generation loop checks via [`interrupted`]. A no-op without
+/// the `sigint` feature.
+pub fn install_handler() {
+    #[cfg(all(unix, feature = "sigint"))]
+    // SAFETY: the handler only performs an atomic store, which is async
+    // signal safe.
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
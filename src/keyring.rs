@@ -0,0 +1,147 @@
+//! Storing generated passwords in the keychain of the operating system.
+//!
+//! Rather than linking against the platform libraries this drives the
+//! standard command line clients, `secret-tool` (libsecret) on Linux and the
+//! BSDs and `security` on macOS. The password is passed to them on stdin so
+//! it never shows up in the process list.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    str::FromStr,
+};
+
+/// Where to store a password, given as `<service>:<account>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub service: String,
+    pub account: String,
+}
+
+impl FromStr for Target {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((service, account)) if !service.is_empty() && !account.is_empty() => Ok(Target {
+                service: service.to_string(),
+                account: account.to_string(),
+            }),
+            _ => Err(format!("expected <service>:<account>, got '{s}'")),
+        }
+    }
+}
+
+/// Save `secret` for `target`, replacing an existing entry.
+pub fn store(target: &Target, secret: &str) -> Result<(), String> {
+    if !cfg!(feature = "keyring") {
+        return Err("gen-pw was built without the keyring feature".to_string());
+    }
+    if cfg!(target_os = "macos") {
+        // In interactive mode `security` reads the command from stdin
+        let command = format!(
+            "add-generic-password -U -s {} -a {} -w {}\n",
+            quote(&target.service),
+            quote(&target.account),
+            quote(secret)
+        );
+        run(Command::new("security").arg("-i"), &command)
+    } else if cfg!(unix) {
+        run(
+            Command::new("secret-tool")
+                .arg("store")
+                .arg(format!("--label={} ({})", target.service, target.account))
+                .args(["service", &target.service, "account", &target.account]),
+            secret,
+        )
+    } else {
+        Err("storing passwords in the keyring is not supported on this platform".to_string())
+    }
+}
+
+/// Double quote `s` for the command parser of `security -i`.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn run(command: &mut Command, input: &str) -> Result<(), String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                format!("Could not store the password, {program} is not installed")
+            }
+            _ => format!("Could not run {program}: {e}"),
+        })?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .map_err(|e| format!("Could not pass the password to {program}: {e}"))?;
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Could not run {program}: {e}"))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{program} failed to store the password: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_targets() {
+        assert_eq!(
+            "mail:alice@example.com".parse(),
+            Ok(Target {
+                service: "mail".to_string(),
+                account: "alice@example.com".to_string(),
+            })
+        );
+        // Only the first colon separates them
+        assert_eq!("db:admin:1".parse::<Target>().unwrap().account, "admin:1");
+        for invalid in ["mail", ":alice", "mail:", ":", ""] {
+            assert_eq!(
+                invalid.parse::<Target>(),
+                Err(format!("expected <service>:<account>, got '{invalid}'"))
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_failing_clients() {
+        let failing = run(
+            Command::new("sh").args([
+                "-c",
+                "cat > /dev/null; echo 'keyring is locked' >&2; exit 1",
+            ]),
+            "secret",
+        );
+        assert_eq!(
+            failing,
+            Err("sh failed to store the password: keyring is locked".to_string())
+        );
+        assert_eq!(
+            run(Command::new("sh").args(["-c", "cat > /dev/null"]), "secret"),
+            Ok(())
+        );
+        assert_eq!(
+            run(&mut Command::new("gen-pw-test-no-such-client"), "secret"),
+            Err(
+                "Could not store the password, gen-pw-test-no-such-client is not installed"
+                    .to_string()
+            )
+        );
+    }
+}
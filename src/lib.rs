@@ -0,0 +1,5752 @@
+//! Generation of random passwords satisfying a set of constraints.
+//!
+//! The options are described by [`Config`], usually parsed from command line
+//! arguments with [`clap::Parser`]. [`validate`] checks them without
+//! generating anything and [`passwords`] produces an endless stream of
+//! passwords.
+
+extern crate clap;
+extern crate either;
+extern crate rand;
+#[macro_use]
+extern crate log;
+
+pub mod completions;
+pub mod config;
+pub mod filter;
+pub mod graphemes;
+pub mod interrupt;
+#[cfg(feature = "kdf")]
+mod kdf;
+pub mod keyring;
+pub mod policy;
+pub mod selftest;
+pub mod words;
+
+use std::{
+    cell::Cell,
+    char::{ToLowercase, ToUppercase},
+    collections::HashSet,
+    fmt,
+    path::PathBuf,
+    rc::Rc,
+    str::FromStr,
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use either::Either;
+use graphemes::grapheme_len;
+use words::WordSource;
+
+use rand::{prelude::Distribution, seq::SliceRandom, RngCore, SeedableRng};
+
+/// Options of the commands that sample dictionary words.
+#[derive(clap::Args)]
+pub struct DictArgs {
+    #[clap(long, env, default_value = "en")]
+    pub language: String,
+    /// Transformation applied to each sampled word before it is appended
+    #[clap(long, value_enum, default_value = "none")]
+    pub word_transform: WordTransform,
+    /// Also sample words containing apostrophes (e.g. "don't"). The
+    /// apostrophe only counts as a symbol if it is in `--symbols`.
+    #[clap(long)]
+    pub keep_apostrophes: bool,
+    /// Capitalize only the first word and write all others in lower case,
+    /// like a sentence
+    #[clap(long)]
+    pub title_case: bool,
+    /// Normalize the dictionary words before sampling them, can be given
+    /// more than once
+    #[clap(long, value_enum)]
+    pub dict_preprocess: Vec<DictPreprocess>,
+    /// Change the case of letters with the rules of this language instead
+    /// of the Unicode defaults, e.g. `i` becomes `İ` in Turkish
+    #[clap(long, value_enum, value_name = "LOCALE")]
+    pub casing_locale: Option<CasingLocale>,
+    /// Refuse to start if picking one word from the filtered dictionary
+    /// yields less than this entropy, e.g. because the word list is tiny
+    #[clap(long, value_name = "BITS")]
+    pub min_per_word_entropy: Option<f64>,
+    /// Keep the case of words that already have a letter of the case they
+    /// were picked for, e.g. acronyms, and only change the first letter of
+    /// the others
+    #[clap(long, conflicts_with = "title-case")]
+    pub preserve_case: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Select the letter portion of the password by sampling words from a dictionary
+    Dict {
+        #[clap(flatten)]
+        dict: DictArgs,
+        /// Use at least this many words, even if the password is long enough
+        /// with fewer
+        #[clap(long, value_name = "N")]
+        min_words: Option<usize>,
+        /// Use at most this many words, longer candidates are rejected
+        #[clap(long, value_name = "N")]
+        max_words: Option<usize>,
+        /// Require at least this many words of `--long-word-threshold` or
+        /// more letters, so the passphrase is not made of tiny words only
+        #[clap(long, value_name = "N")]
+        min_long_words: Option<usize>,
+        /// Number of letters from which on a word counts as long
+        #[clap(long, value_name = "L", default_value = "6")]
+        long_word_threshold: usize,
+        /// Finish a passphrase with up to 2 random letters when no word fits
+        /// the rest of the length budget, instead of starting over
+        #[clap(long)]
+        pad_with_chars: bool,
+        /// Reject passphrases that use a word again within this many words,
+        /// e.g. 1 only forbids the same word twice in a row
+        #[clap(long, value_name = "K")]
+        no_repeat_within: Option<usize>,
+    },
+    /// Select the letter portion of the password by just randomly selecting
+    /// (a-z) letters.
+    Chars,
+    /// Dictionary words followed by random characters, e.g. `correct$Kx9`.
+    /// The random characters are picked like in `chars`.
+    Mixed {
+        #[clap(flatten)]
+        dict: DictArgs,
+        /// Number of dictionary words at the start of the password
+        #[clap(long, default_value = "1")]
+        word_count: usize,
+        /// Number of random characters after the words
+        #[clap(long, default_value = "4")]
+        random_chars: usize,
+    },
+    /// A single capitalized dictionary word of at most 6 letters padded with
+    /// numbers, e.g. `Horse42`, for legacy systems with short length limits.
+    /// Ignores the length options and constraints.
+    Compact {
+        #[clap(long, env, default_value = "en")]
+        language: String,
+        /// Exact length of the password
+        #[clap(long, default_value = "8")]
+        length: usize,
+    },
+    /// A numeric code in groups, e.g. `483 921`, like the ones of two factor
+    /// authentication. Ignores the length options and required classes, but
+    /// checks like `--no-leading-zero` apply. The grouping is only part of
+    /// the plain output.
+    Code {
+        /// Digits per group
+        #[clap(long, default_value = "3")]
+        group_size: usize,
+        /// Number of groups
+        #[clap(long, default_value = "2")]
+        groups: usize,
+    },
+    /// Deterministically derive the password for a site from a master
+    /// secret. The same secret, site and options always yield the same
+    /// password. Letters are selected like in `chars`.
+    Derive {
+        /// Name of the site or account the password is for
+        site: String,
+        /// The master secret the password is derived from
+        #[clap(long, env = "GEN_PW_MASTER", hide_env_values = true)]
+        master: String,
+        /// Increase to rotate the password of a site without changing the
+        /// master secret
+        #[clap(long, default_value = "0")]
+        revision: u32,
+    },
+    /// Check that the generator works on this system by generating passwords
+    /// with a range of options and verifying them. Ignores all other
+    /// options.
+    SelfTest,
+    /// Print uniformly sampled dictionary words, one per line, e.g. for
+    /// naming things. Only the dictionary options and `--max` apply.
+    Words {
+        #[clap(flatten)]
+        dict: DictArgs,
+        /// Number of words to print
+        #[clap(default_value = "10")]
+        count: usize,
+    },
+    /// Compare the strength of the options in two configuration files (see
+    /// `--from-config`) by generating a sample of passwords with each. The
+    /// options before `compare` apply to both.
+    Compare { first: PathBuf, second: PathBuf },
+    /// Generate as many passwords as possible for a while and report how
+    /// many per second and the mean number of tries. Benchmarks the command
+    /// of a configuration file (see `--from-config`) if one is given, `chars`
+    /// otherwise.
+    Benchmark {
+        config: Option<PathBuf>,
+        /// How long to generate passwords
+        #[clap(long, default_value = "3")]
+        duration_secs: u64,
+    },
+    /// Print a completion script for the options to stdout, e.g.
+    /// `gen-pw completions bash > /etc/bash_completion.d/gen-pw`
+    Completions {
+        #[clap(value_enum)]
+        shell: completions::Shell,
+    },
+    /// Print the commands, constraints, output formats and options this
+    /// version supports as JSON, for tools that wrap it
+    Capabilities,
+}
+
+#[derive(Clone, ValueEnum, PartialEq, Copy, Debug)]
+pub enum Constraint {
+    LowerCaseLetter,
+    UpperCaseLetter,
+    Number,
+    Symbol,
+}
+
+#[derive(Clone, ValueEnum, PartialEq, Copy, Debug)]
+pub enum KeyboardLayout {
+    /// US QWERTY
+    Us,
+    /// French AZERTY
+    Azerty,
+    /// German QWERTZ
+    Qwertz,
+}
+
+#[derive(Clone, ValueEnum, PartialEq, Copy, Debug)]
+pub enum ClassesOrder {
+    /// Place one pick of every required class at a random position and
+    /// draw the other positions by the class weights
+    Shuffle,
+    /// Cycle through the required classes in order, e.g. lower, upper,
+    /// number, symbol, lower, ...
+    RoundRobin,
+    /// Draw every position by the class weights, e.g. from
+    /// `--symbol-probability`, and reject candidates missing a class
+    Weighted,
+}
+
+#[derive(Clone, ValueEnum, PartialEq, Copy, Debug)]
+pub enum RngKind {
+    /// ChaCha20, seeded with `--seed` for reproducible output or from the
+    /// operating system otherwise. Used by `derive`.
+    Chacha,
+    /// Ask the operating system for every random number. Slowest, but does
+    /// not keep any random state in the process.
+    Os,
+    /// The thread local generator of the rand crate, a ChaCha variant that
+    /// the operating system reseeds periodically
+    Thread,
+}
+
+#[derive(Clone, ValueEnum, PartialEq, Copy, Debug)]
+pub enum CasingLocale {
+    /// Turkish, with dotted `i`/`İ` and dotless `ı`/`I`
+    Tr,
+    /// Azerbaijani, which cases `i` like Turkish
+    Az,
+}
+
+/// `c` in upper case, with the special mappings of `locale` if given.
+fn to_upper(c: char, locale: Option<CasingLocale>) -> Either<std::iter::Once<char>, ToUppercase> {
+    match (locale, c) {
+        (Some(CasingLocale::Tr | CasingLocale::Az), 'i') => Either::Left(std::iter::once('İ')),
+        _ => Either::Right(c.to_uppercase()),
+    }
+}
+
+/// `c` in lower case, with the special mappings of `locale` if given.
+fn to_lower(c: char, locale: Option<CasingLocale>) -> Either<std::iter::Once<char>, ToLowercase> {
+    match (locale, c) {
+        (Some(CasingLocale::Tr | CasingLocale::Az), 'I') => Either::Left(std::iter::once('ı')),
+        (Some(CasingLocale::Tr | CasingLocale::Az), 'İ') => Either::Left(std::iter::once('i')),
+        _ => Either::Right(c.to_lowercase()),
+    }
+}
+
+#[derive(Clone, ValueEnum, PartialEq, Copy, Debug)]
+pub enum DictPreprocess {
+    /// Lower case all words, e.g. proper nouns, before the case of their
+    /// first letter is chosen
+    Lowercase,
+    /// Replace accented Latin letters with their ASCII base letters, e.g.
+    /// `café` becomes `cafe`
+    StripAccents,
+}
+
+#[derive(Clone, ValueEnum, PartialEq, Copy, Debug)]
+pub enum OutputFormat {
+    /// Just the password
+    Plain,
+    /// A POSIX shell assignment `NAME='password'` for `eval`, see `--var`
+    Env,
+    /// A JSON object per password with its entropy, broken down by where it
+    /// comes from
+    Json,
+}
+
+#[derive(Clone, ValueEnum, PartialEq, Copy, Debug)]
+pub enum DictSource {
+    /// The master dictionary of the aspell `--language`
+    Aspell,
+    /// An installed WordNet database, always English
+    Wordnet,
+    /// The word list given with `--dict-file`
+    File,
+}
+
+#[derive(Clone, ValueEnum, PartialEq, Copy, Debug)]
+pub enum WordTransform {
+    None,
+    /// Reverse the letters of the word
+    Reverse,
+    /// Remove all vowels from the word
+    DropVowels,
+    /// Randomly change the case of every letter
+    RandomCase,
+}
+
+impl WordTransform {
+    /// Transform `word`, returning the result and the entropy in bits the
+    /// transformation added.
+    fn apply<R: rand::Rng + ?Sized>(
+        self,
+        word: &str,
+        locale: Option<CasingLocale>,
+        rng: &mut R,
+    ) -> (String, f64) {
+        match self.apply_fixed(word) {
+            Some(word) => (word, 0.0),
+            None => {
+                let mut bits = 0.0;
+                let transformed = word
+                    .chars()
+                    .flat_map(|c| {
+                        if c.is_lowercase() || c.is_uppercase() {
+                            bits += 1.0;
+                        }
+                        if rng.gen() {
+                            Either::Left(to_lower(c, locale))
+                        } else {
+                            Either::Right(to_upper(c, locale))
+                        }
+                    })
+                    .collect();
+                (transformed, bits)
+            }
+        }
+    }
+
+    /// Transform `word` if the transformation does not depend on chance.
+    fn apply_fixed(self, word: &str) -> Option<String> {
+        match self {
+            WordTransform::None => Some(word.to_string()),
+            WordTransform::Reverse => Some(word.chars().rev().collect()),
+            WordTransform::DropVowels => Some(
+                word.chars()
+                    .filter(|c| !"aeiouAEIOU".contains(*c))
+                    .collect(),
+            ),
+            WordTransform::RandomCase => None,
+        }
+    }
+}
+
+impl Command {
+    /// The name of the command on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Dict { .. } => "dict",
+            Command::Chars => "chars",
+            Command::Mixed { .. } => "mixed",
+            Command::Compact { .. } => "compact",
+            Command::Code { .. } => "code",
+            Command::Derive { .. } => "derive",
+            Command::SelfTest => "self-test",
+            Command::Words { .. } => "words",
+            Command::Compare { .. } => "compare",
+            Command::Benchmark { .. } => "benchmark",
+            Command::Completions { .. } => "completions",
+            Command::Capabilities => "capabilities",
+        }
+    }
+
+    fn weights(&self, constraint: Constraint) -> u8 {
+        match self {
+            Command::Chars
+            | Command::Derive { .. }
+            | Command::Compact { .. }
+            | Command::Code { .. }
+            | Command::Mixed { .. }
+            | Command::SelfTest
+            | Command::Compare { .. }
+            | Command::Benchmark { .. }
+            | Command::Completions { .. }
+            | Command::Capabilities
+            | Command::Words { .. } => 1,
+            Command::Dict { .. } => match constraint {
+                Constraint::LowerCaseLetter | Constraint::UpperCaseLetter => 2,
+                Constraint::Number | Constraint::Symbol => 1,
+            },
+        }
+    }
+
+    /// Bounds on the number of dictionary words in a password.
+    fn word_band(&self) -> (usize, Option<usize>) {
+        match self {
+            // Long words are words as well
+            Command::Dict {
+                min_words,
+                max_words,
+                min_long_words,
+                ..
+            } => (
+                min_words.unwrap_or(0).max(min_long_words.unwrap_or(0)),
+                *max_words,
+            ),
+            _ => (0, None),
+        }
+    }
+
+    /// The required number of long words and the length from which on a
+    /// word is long, for `--min-long-words`.
+    fn long_words(&self) -> Option<(usize, usize)> {
+        match self {
+            Command::Dict {
+                min_long_words: Some(n),
+                long_word_threshold,
+                ..
+            } => Some((*n, *long_word_threshold)),
+            _ => None,
+        }
+    }
+
+    /// The window of `--no-repeat-within`.
+    fn repeat_window(&self) -> Option<usize> {
+        match self {
+            Command::Dict {
+                no_repeat_within, ..
+            } => *no_repeat_within,
+            _ => None,
+        }
+    }
+
+    /// The dictionary options, for the commands sampling words.
+    fn dict_args(&self) -> Option<&DictArgs> {
+        match self {
+            Command::Dict { dict, .. }
+            | Command::Mixed { dict, .. }
+            | Command::Words { dict, .. } => Some(dict),
+            _ => None,
+        }
+    }
+}
+
+impl Constraint {
+    fn verify(self, opts: &Opts, s: &str) -> bool {
+        s.chars().any(|c| self.contains(opts, c))
+            || (self == Constraint::Symbol && opts.emoji.iter().any(|e| s.contains(e.as_str())))
+    }
+
+    /// Whether the pool of the class contains `c`. Emoji are not single
+    /// characters and never match.
+    fn contains(self, opts: &Opts, c: char) -> bool {
+        match self {
+            Constraint::LowerCaseLetter => opts.lower_chars.contains(c),
+            Constraint::UpperCaseLetter => opts.upper_chars.contains(c),
+            Constraint::Number => opts.number_chars.contains(c),
+            Constraint::Symbol => opts.symbols.contains(c),
+        }
+    }
+}
+
+/// The classes a string has at least one character of.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+struct ClassSet(u8);
+
+impl ClassSet {
+    /// Classifies the characters of `s` in a single pass. Agrees with
+    /// [`Constraint::verify`] for every class.
+    fn of(opts: &Opts, s: &str) -> Self {
+        let mut set = ClassSet::default();
+        for c in s.chars() {
+            for class in Constraint::value_variants() {
+                if class.contains(opts, c) {
+                    set.insert(*class);
+                }
+            }
+        }
+        if !set.contains(Constraint::Symbol) && opts.emoji.iter().any(|e| s.contains(e.as_str())) {
+            set.insert(Constraint::Symbol);
+        }
+        set
+    }
+
+    fn insert(&mut self, class: Constraint) {
+        self.0 |= 1 << class as u8;
+    }
+
+    fn extend(&mut self, other: ClassSet) {
+        self.0 |= other.0;
+    }
+
+    fn contains(self, class: Constraint) -> bool {
+        self.0 & (1 << class as u8) != 0
+    }
+}
+
+impl Constraint {
+    /// The class a single character (grapheme cluster) of a password belongs
+    /// to, if any.
+    fn classify(opts: &Opts, grapheme: &str) -> Option<Constraint> {
+        if opts.emoji.iter().any(|e| e == grapheme) {
+            return Some(Constraint::Symbol);
+        }
+        // Combining marks etc. do not change the class of the base character
+        let base = &grapheme[..grapheme.chars().next()?.len_utf8()];
+        Constraint::value_variants()
+            .iter()
+            .copied()
+            .find(|class| class.verify(opts, base))
+    }
+}
+
+impl FromStr for Constraint {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Self as ValueEnum>::from_str(s, true)
+    }
+}
+
+/// A class the character at a position must belong to, given as
+/// `<index>:<class>`. Indices count from 1, negative ones from the end.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PositionConstraint {
+    pub index: isize,
+    pub class: Constraint,
+}
+
+impl FromStr for PositionConstraint {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (index, class) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected <index>:<class>, got '{s}'"))?;
+        let index = index
+            .parse::<isize>()
+            .map_err(|e| format!("invalid index '{index}': {e}"))?;
+        if index == 0 {
+            return Err("positions count from 1, or from -1 at the end".to_string());
+        }
+        Ok(PositionConstraint {
+            index,
+            class: class.parse()?,
+        })
+    }
+}
+
+impl PositionConstraint {
+    fn verify(self, opts: &Opts, s: &str) -> bool {
+        let graphemes = graphemes::graphemes(s).collect::<Vec<_>>();
+        let position = if self.index > 0 {
+            self.index.unsigned_abs() - 1
+        } else {
+            match graphemes.len().checked_sub(self.index.unsigned_abs()) {
+                Some(position) => position,
+                None => return false,
+            }
+        };
+        graphemes
+            .get(position)
+            .is_some_and(|g| Constraint::classify(opts, g) == Some(self.class))
+    }
+}
+
+/// A named group of symbols, given as `<name>=<symbols>`, whose number in a
+/// password `--symbol-tier-min` and `--symbol-tier-max` limit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SymbolTier {
+    pub name: String,
+    pub symbols: String,
+}
+
+impl FromStr for SymbolTier {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, symbols) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <name>=<symbols>, got '{s}'"))?;
+        if name.is_empty() || symbols.is_empty() {
+            return Err(format!("expected <name>=<symbols>, got '{s}'"));
+        }
+        Ok(SymbolTier {
+            name: name.to_string(),
+            symbols: symbols.to_string(),
+        })
+    }
+}
+
+impl SymbolTier {
+    /// How many characters of `s` belong to the tier.
+    fn count(&self, s: &str) -> usize {
+        let symbols = graphemes::graphemes(&self.symbols).collect::<Vec<_>>();
+        graphemes::graphemes(s)
+            .filter(|g| symbols.contains(g))
+            .count()
+    }
+}
+
+/// A bound on the symbols of a tier, given as `<name>=<count>`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TierCount {
+    pub name: String,
+    pub count: usize,
+}
+
+impl FromStr for TierCount {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, count) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <name>=<count>, got '{s}'"))?;
+        Ok(TierCount {
+            name: name.to_string(),
+            count: count
+                .parse()
+                .map_err(|e| format!("invalid count '{count}': {e}"))?,
+        })
+    }
+}
+
+/// The required classes given with `--classes`, see [`parse_classes`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Classes(pub Vec<Constraint>);
+
+impl FromStr for Classes {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_classes(s).map(Classes)
+    }
+}
+
+/// Parse a class specification like `+lower+upper-symbol` into the required
+/// classes. Classes are given by their full names or as `lower`, `upper`,
+/// `number` and `symbol`.
+pub fn parse_classes(s: &str) -> Result<Vec<Constraint>, String> {
+    let mut added = vec![];
+    let mut removed = vec![];
+    let mut rest = s;
+    while !rest.is_empty() {
+        let sign = rest.chars().next().unwrap();
+        if sign != '+' && sign != '-' {
+            return Err(format!("expected + or - before '{rest}' in '{s}'"));
+        }
+        let body = &rest[1..];
+        let end = body.find(['+', '-']).unwrap_or(body.len());
+        // Full class names contain dashes themselves
+        let end = ["lower-case-letter", "upper-case-letter"]
+            .iter()
+            .find(|name| body.starts_with(*name))
+            .map_or(end, |name| name.len());
+        let name = &body[..end];
+        rest = &body[end..];
+        let class = match name {
+            "" => return Err(format!("missing class after '{sign}' in '{s}'")),
+            "lower" => Constraint::LowerCaseLetter,
+            "upper" => Constraint::UpperCaseLetter,
+            _ => name
+                .parse()
+                .map_err(|_| format!("unknown class '{name}' in '{s}'"))?,
+        };
+        let list = if sign == '+' {
+            &mut added
+        } else {
+            &mut removed
+        };
+        if !list.contains(&class) {
+            list.push(class);
+        }
+    }
+    if added.is_empty() && removed.is_empty() {
+        return Err("no classes given".to_string());
+    }
+    if added.is_empty() {
+        added = Constraint::value_variants().to_vec();
+    }
+    added.retain(|class| !removed.contains(class));
+    Ok(added)
+}
+
+pub const LOWER_CASE_LETTERS: &str = "abcdefghijklmnopqrstuvwxyz";
+pub const UPPER_CASE_LETTERS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+pub const NUMBERS: &str = "0123456789";
+pub const SYMBOLS: &str = "-_/[]{}()*&^%$#@.!?=+:;|~";
+/// How often the dict mode samples a word before it gives up on finding one
+/// that still fits into the remaining length budget of a candidate.
+const WORD_PICK_ATTEMPTS: usize = 100;
+/// Longest word the `compact` command uses
+const COMPACT_MAX_WORD_LEN: usize = 6;
+/// Number of words up to which the dict mode checks whether any combination
+/// of them fits the length range before it starts generating
+const FEASIBILITY_MAX_WORDS: usize = 4;
+/// Most letters `--pad-with-chars` adds in place of a word
+const PAD_MAX_CHARS: usize = 2;
+/// Shortest dictionary word `--reject-random-words` looks for, shorter ones
+/// occur by chance in almost every password
+const RANDOM_WORD_MIN_LEN: usize = 4;
+/// From this `--count` on `--post-filter` warns that it is slow
+const POST_FILTER_SLOW_COUNT: usize = 100;
+/// Characters of a rejected candidate shown by `--debug`
+const DEBUG_CANDIDATE_LEN: usize = 64;
+
+/// Generate strong passwords by sampling words form an aspell dictionary or
+/// just letters.
+#[derive(Parser)]
+#[clap(author, version, about)]
+pub struct Opts {
+    /// Minimal length of the password
+    #[clap(long, default_value = "10")]
+    pub min: usize,
+    /// Maximal length of the password
+    #[clap(long, default_value = "20")]
+    pub max: usize,
+    /// Aim for a length of about N instead of giving `--min` and `--max`,
+    /// give or take `--jitter`
+    #[clap(long, value_name = "N", conflicts_with_all = &["min", "max"])]
+    pub target_length: Option<usize>,
+    /// How far the length may deviate from `--target-length`, as a fraction
+    /// of it. `--target-length 16 --jitter 0.2` allows 13 to 19 characters.
+    #[clap(long, value_name = "P", value_parser = parse_probability, requires = "target-length")]
+    pub jitter: Option<f64>,
+    /// Refuse to generate passwords longer than this, guarding against
+    /// typos like `--max 100000000`
+    #[clap(long, value_name = "N", default_value = "4096")]
+    pub max_length_hard_cap: usize,
+    /// Require this constraint be fulfilled, if left empty all constraints are required.
+    #[clap(long)]
+    pub require: Vec<Constraint>,
+    /// Exclude this constraint. Overwrites both default and elements in `require`
+    #[clap(long)]
+    pub exclude: Vec<Constraint>,
+    /// Require both a lower and an upper case letter, short for `--require
+    /// lower-case-letter --require upper-case-letter` in addition to any
+    /// other `--require`
+    #[clap(long, conflicts_with = "classes")]
+    pub require_mixed_case: bool,
+    /// The required classes in one argument, e.g. `+lower+upper+number-symbol`.
+    /// `+` requires a class and `-` excludes it. Without any `+` all classes
+    /// not excluded are required. Replaces `--require` and `--exclude`.
+    #[clap(long, value_name = "SPEC", allow_hyphen_values = true, conflicts_with_all = &["require", "exclude"])]
+    pub classes: Option<Classes>,
+    /// Only use letters, equivalent to excluding `number` and `symbol`
+    #[clap(long, conflicts_with = "no-letters")]
+    pub letters_only: bool,
+    /// Do not use letters, equivalent to excluding `lower-case-letter` and
+    /// `upper-case-letter`
+    #[clap(long)]
+    pub no_letters: bool,
+    /// Characters to use as the valid symbols
+    #[clap(long, alias = "symbol-chars", default_value = SYMBOLS)]
+    pub symbols: String,
+    /// Read the valid symbols from this file instead
+    #[clap(long, conflicts_with = "symbols")]
+    pub symbols_file: Option<PathBuf>,
+    /// Keep spaces in the symbols. All whitespace is removed from the
+    /// symbols by default because many systems reject it in passwords.
+    #[clap(long)]
+    pub allow_space_symbol: bool,
+    /// Characters that count as lower case letters
+    #[clap(long, default_value = LOWER_CASE_LETTERS)]
+    pub lower_chars: String,
+    /// Characters that count as upper case letters
+    #[clap(long, default_value = UPPER_CASE_LETTERS)]
+    pub upper_chars: String,
+    /// Characters that count as numbers
+    #[clap(long, default_value = NUMBERS)]
+    pub number_chars: String,
+    /// Additional (possibly multi-codepoint) symbols such as emoji, separated
+    /// by commas. With the `graphemes` feature, which is on by default, each
+    /// counts as a single character towards the length.
+    #[clap(long, use_value_delimiter = true)]
+    pub emoji: Vec<String>,
+    /// Probability of picking a symbol for each position in `chars` mode.
+    /// Classes without a fixed probability share the remainder equally.
+    #[clap(long, value_name = "P", value_parser = parse_probability)]
+    pub symbol_probability: Option<f64>,
+    /// Probability of picking a number for each position in `chars` mode
+    #[clap(long, value_name = "P", value_parser = parse_probability)]
+    pub number_probability: Option<f64>,
+    #[clap(long, default_value = "1000")]
+    pub tries: usize,
+    /// When no candidate satisfied all constraints within `--tries`, return
+    /// the longest candidate that fell short of `--min` but satisfies all
+    /// other constraints instead of failing. This trades strictness for
+    /// reliability, e.g. when dictionary words cannot fill the length range
+    /// exactly.
+    #[clap(long)]
+    pub allow_shorter_than_min_if_exhausted: bool,
+    /// When no candidate passes within `--tries`, drop the next soft check
+    /// and try again, reporting each dropped check on stderr. The length
+    /// limits and the required classes are never relaxed.
+    #[clap(long)]
+    pub auto_relax: bool,
+    /// Reject any password containing non-ASCII characters, e.g. from
+    /// dictionary words, custom pools or emoji
+    #[clap(long)]
+    pub ascii_only: bool,
+    /// Reject passwords starting with `0`, which some systems strip or
+    /// reject in numeric codes
+    #[clap(long)]
+    pub no_leading_zero: bool,
+    /// Reject passwords that do not start with a letter or digit that has
+    /// no lookalike, e.g. for passwords read out over the phone
+    #[clap(long)]
+    pub require_non_ambiguous_start: bool,
+    /// Reject passwords that contain this user name, ignoring case
+    #[clap(long, value_name = "NAME")]
+    pub username: Option<String>,
+    /// Also reject the user name written with common character
+    /// substitutions, e.g. `adm1n` or `@dmin` for `admin`
+    #[clap(long, requires = "username")]
+    pub username_leet: bool,
+    /// Reject passwords whose random characters spell a dictionary word of
+    /// at least 4 letters, in `chars` and `mixed`. This loads the dictionary
+    /// and checks every substring of each candidate, so it is noticeably
+    /// slower.
+    #[clap(long)]
+    pub reject_random_words: bool,
+    /// Pass each candidate that satisfies all other constraints to this
+    /// program on stdin and reject it unless the program exits with 0. This
+    /// starts a process per candidate, which is slow.
+    #[clap(long, value_name = "PROGRAM")]
+    pub post_filter: Option<PathBuf>,
+    /// Require at least N numbers picked at random, not counting digits
+    /// that are part of dictionary words
+    #[clap(long, value_name = "N")]
+    pub min_random_digits: Option<usize>,
+    /// Require at least N different symbols, repeating a symbol does not
+    /// count
+    #[clap(long, value_name = "N")]
+    pub min_distinct_symbols: Option<usize>,
+    /// Name a group of symbols, e.g. `common=!@#`, to bound how many of
+    /// them a password has with `--symbol-tier-min` and
+    /// `--symbol-tier-max`. Can be given more than once.
+    #[clap(long, value_name = "NAME=SYMBOLS")]
+    pub symbol_tier: Vec<SymbolTier>,
+    /// Require at least this many symbols of a `--symbol-tier`, e.g.
+    /// `common=1`
+    #[clap(long, value_name = "NAME=N")]
+    pub symbol_tier_min: Vec<TierCount>,
+    /// Allow at most this many symbols of a `--symbol-tier`, e.g.
+    /// `uncommon=1`
+    #[clap(long, value_name = "NAME=N")]
+    pub symbol_tier_max: Vec<TierCount>,
+    /// Reject passwords with more than this many consecutive characters of
+    /// the same class, e.g. no more than 2 numbers in a row
+    #[clap(long, value_name = "N")]
+    pub max_class_run: Option<usize>,
+    /// Reject `chars` passwords with more than this many vowels in a row
+    #[clap(long, value_name = "N")]
+    pub max_consecutive_vowels: Option<usize>,
+    /// Reject `chars` passwords with more than this many consonants in a
+    /// row, which makes them easier to type
+    #[clap(long, value_name = "N")]
+    pub max_consecutive_consonants: Option<usize>,
+    /// Require the character at a position to be of a class, e.g. `1:upper-case-letter`
+    /// or `-1:number` for the last one. Can be given more than once.
+    #[clap(long, value_name = "INDEX:CLASS", allow_hyphen_values = true)]
+    pub position_constraint: Vec<PositionConstraint>,
+    /// Use the old generation algorithm, which samples every character class
+    /// at random and rejects candidates missing a required class. By default
+    /// each required class is placed at least once by construction and only
+    /// the length and secondary checks can cause a rejection.
+    #[clap(long)]
+    pub legacy_gen: bool,
+    /// How the classes are distributed over the positions of the password
+    #[clap(long, value_enum, default_value = "shuffle")]
+    pub classes_order: ClassesOrder,
+    /// Avoid repeating characters: numbers, symbols and (in `chars` mode)
+    /// letters are picked among the ones not yet in the password as long as
+    /// there are any
+    #[clap(long)]
+    pub maximize_diversity: bool,
+    /// Generate K valid passwords and keep the one with the highest
+    /// estimated entropy, which helps when the length varies, e.g. in `dict`
+    #[clap(long, value_name = "K")]
+    pub prefer_strong: Option<usize>,
+    /// Number of passwords to generate, one per line
+    #[clap(long, default_value = "1")]
+    pub count: usize,
+    /// Generate this many passwords and report how often each character
+    /// class occurs compared to how often it is picked in theory
+    #[clap(long, hide = true, value_name = "SAMPLES")]
+    pub analyze_bias: Option<usize>,
+    /// Generate this many passwords and count why the rejected candidates
+    /// were rejected, to find the constraint that is hardest to satisfy
+    #[clap(long, value_name = "SAMPLES")]
+    pub explain_rejections: Option<usize>,
+    /// The random number generator to use. Defaults to `chacha` with
+    /// `--seed` and to `thread` otherwise.
+    #[clap(long, value_enum)]
+    pub rng: Option<RngKind>,
+    /// Seed the random number generator to make the output reproducible.
+    /// Note that the output then has at most 64 bits of entropy and anyone
+    /// who knows the seed can reproduce it.
+    #[clap(long)]
+    pub seed: Option<u64>,
+    /// Seed the random number generator with the SHA-256 hash of this file,
+    /// e.g. a managed secret shared by a team. The same file always yields
+    /// the same passwords.
+    #[clap(long, value_name = "PATH", conflicts_with_all = &["seed", "show-seed"])]
+    pub seed_from_file: Option<PathBuf>,
+    /// The hash of `--seed-from-file`, read by [`Opts::normalize`]
+    #[clap(skip)]
+    pub file_seed: Option<[u8; 32]>,
+    /// Print the seed of the random number generator to stderr, so the run
+    /// can be reproduced with `--seed`. Picks a random seed if none is given.
+    #[clap(long)]
+    pub show_seed: bool,
+    /// Print this many numbered candidates to choose from instead of a
+    /// single password
+    #[clap(long, value_name = "N")]
+    pub preview: Option<usize>,
+    /// Print N numbered candidates like `--preview` and a token on stderr,
+    /// so that a later run with the same options, `--choose` and `--token`
+    /// can print the chosen one again
+    #[clap(
+        long,
+        value_name = "N",
+        conflicts_with_all = &["seed", "seed-from-file", "preview", "count"]
+    )]
+    pub choose_from: Option<usize>,
+    /// Print candidate K of the `--choose-from` run with `--token`
+    #[clap(
+        long,
+        value_name = "K",
+        requires = "token",
+        conflicts_with_all = &["seed", "seed-from-file", "count", "choose-from"]
+    )]
+    pub choose: Option<usize>,
+    /// The token printed by `--choose-from`
+    #[clap(long, requires = "choose")]
+    pub token: Option<u64>,
+    /// Print the estimated entropy and the time needed to crack the password
+    /// to stderr
+    #[clap(long)]
+    pub show_cracktime: bool,
+    /// Print the words, letters, numbers and symbols the password was
+    /// assembled from to stderr, in order
+    #[clap(long)]
+    pub explain: bool,
+    /// Print a legend for characters in the password that are easily
+    /// confused with others, such as `l` and `1`, to stderr
+    #[clap(long)]
+    pub annotate: bool,
+    /// Warn on stderr about characters in the password that need Shift,
+    /// AltGr or a dead key on this keyboard layout. Only advisory, the
+    /// password is printed all the same.
+    #[clap(long, value_enum, value_name = "LAYOUT")]
+    pub keyboard_layout: Option<KeyboardLayout>,
+    /// Save the password in the keychain of the operating system under
+    /// `<service>:<account>` instead of printing it
+    #[clap(long, value_name = "SERVICE:ACCOUNT", conflicts_with = "count")]
+    pub store_keyring: Option<keyring::Target>,
+    /// Print nothing on stdout, only a confirmation with the length and
+    /// entropy of the password on stderr, for scripts that must never show
+    /// the password, e.g. with `--store-keyring`
+    #[clap(
+        long,
+        conflicts_with_all = &["explain", "annotate", "keyboard-layout"]
+    )]
+    pub confirm_on_stderr: bool,
+    /// Append a JSON line per password to this file, with the time, the
+    /// command, the length and the estimated entropy but never the password
+    #[clap(long, value_name = "PATH")]
+    pub audit_log: Option<PathBuf>,
+    /// Do not print a newline after the last password, so the output is
+    /// exactly the password
+    #[clap(long)]
+    pub no_newline: bool,
+    /// Print the `--count` passwords sorted, which makes near duplicates
+    /// easier to spot. Nothing is printed until all are generated.
+    #[clap(long)]
+    pub sort_output: bool,
+    /// Print each password with this template instead, e.g.
+    /// `"{password} ({entropy} bits, {tries} tries)"`. The placeholders are
+    /// `{password}`, `{entropy}`, `{tries}` and `{length}`, `{{` and `}}`
+    /// print braces.
+    #[clap(long, value_name = "TEMPLATE", conflicts_with_all = &["format", "var"])]
+    pub output_template: Option<String>,
+    /// How to print the password
+    #[clap(long, value_enum, default_value = "plain")]
+    pub format: OutputFormat,
+    /// Name of the variable `--format env` assigns the password to
+    #[clap(
+        long,
+        value_name = "NAME",
+        required_if_eq("format", "env"),
+        conflicts_with = "count"
+    )]
+    pub var: Option<String>,
+    /// Guesses per second assumed for an offline attack (e.g. a leaked hash)
+    #[clap(long, default_value = "1e10")]
+    pub offline_guesses_per_sec: f64,
+    /// Guesses per second assumed for an online attack (e.g. a rate limited
+    /// login form)
+    #[clap(long, default_value = "100")]
+    pub online_guesses_per_sec: f64,
+    /// Fail with exit code 1 instead of printing the password if its
+    /// estimated entropy is below this many bits
+    #[clap(long, value_name = "BITS")]
+    pub min_strength_bits: Option<f64>,
+    /// Where `dict` and `compact` take their words from. Defaults to `file`
+    /// with `--dict-file` and to `aspell` otherwise.
+    #[clap(long, value_enum)]
+    pub dict_source: Option<DictSource>,
+    /// Word list for `--dict-source file`, with whitespace separated words
+    #[clap(long, value_name = "PATH")]
+    pub dict_file: Option<PathBuf>,
+    /// Read `--dict-file` as a frequency list, a word and how often it
+    /// occurs on each line, and drop the words that occur less than N times.
+    /// Higher values keep the passphrases to common, memorable words.
+    #[clap(long, value_name = "N", requires = "dict-file")]
+    pub dict_min_frequency: Option<u64>,
+    /// The aspell binary for `--dict-source aspell`, if it is not the first
+    /// `aspell` on the `PATH`. It has to support `dump master` and `expand`.
+    #[clap(long, env = "ASPELL_PATH", value_name = "PATH")]
+    pub aspell_path: Option<PathBuf>,
+    /// Also satisfy every policy in this TOML file. Each `[name]` table may
+    /// set `min`, `max`, `require`, `exclude`, `symbols` and
+    /// `max-class-run`, the password satisfies their intersection.
+    #[clap(long, value_name = "PATH")]
+    pub policy: Option<PathBuf>,
+    /// Load default options from this TOML file. Keys are the long option
+    /// names, `command` selects the subcommand and a table named after a
+    /// subcommand holds its options. Options given on the command line take
+    /// precedence.
+    #[clap(long)]
+    pub from_config: Option<PathBuf>,
+    #[clap(long, short = 'v', conflicts_with = "quiet")]
+    pub verbose: bool,
+    /// Only report errors, no warnings or progress
+    #[clap(long, short = 'q')]
+    pub quiet: bool,
+    /// Show how many of the `--count` passwords have been generated so far on
+    /// stderr, if it is a terminal
+    #[clap(long)]
+    pub progress: bool,
+    #[clap(long)]
+    pub debug: bool,
+    /// Do not log the "Running with debug" line with `--debug`, e.g. when
+    /// scripts collect the debug output
+    #[clap(long)]
+    pub banner_off: bool,
+    /// Only check that the options are valid and satisfiable, print `OK`
+    /// and exit without generating anything
+    #[clap(long)]
+    pub dry_parse: bool,
+    /// Report how many numbers were drawn from the random number generator
+    /// for each password on stderr
+    #[clap(long)]
+    pub sample_rng_report: bool,
+    /// Print the characters each class is drawn from on stderr, after
+    /// applying the custom pools, policies and exclusions
+    #[clap(long)]
+    pub per_class_pools_report: bool,
+    /// Report how long loading the dictionary, generating the passwords and
+    /// the whole run took on stderr
+    #[clap(long)]
+    pub measure: bool,
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+impl Opts {
+    /// Post-process options after parsing.
+    pub fn normalize(&mut self) -> Result<(), GenError> {
+        if let Some(path) = &self.symbols_file {
+            self.symbols = std::fs::read_to_string(path).map_err(|e| {
+                GenError::Io(format!(
+                    "Could not read symbols file {}: {e}",
+                    path.display()
+                ))
+            })?;
+        }
+        let allow_space = self.allow_space_symbol;
+        self.symbols
+            .retain(|c| !c.is_whitespace() || (allow_space && c == ' '));
+        self.emoji.retain(|e| !e.is_empty());
+        // A repeated character would be picked more often than the others
+        for (option, pool) in [
+            ("--symbols", &mut self.symbols),
+            ("--lower-chars", &mut self.lower_chars),
+            ("--upper-chars", &mut self.upper_chars),
+            ("--number-chars", &mut self.number_chars),
+        ] {
+            let mut seen = HashSet::new();
+            let before = pool.chars().count();
+            pool.retain(|c| seen.insert(c));
+            if seen.len() < before {
+                debug!(
+                    "Removed {} duplicate characters from {option}",
+                    before - seen.len()
+                );
+            }
+        }
+        let mut seen = HashSet::new();
+        let before = self.emoji.len();
+        self.emoji.retain(|e| seen.insert(e.clone()));
+        if seen.len() < before {
+            debug!("Removed {} duplicate emoji", before - seen.len());
+        }
+        if let Some(Classes(classes)) = &self.classes {
+            // An empty `--require` would mean all classes
+            self.exclude = Constraint::value_variants()
+                .iter()
+                .filter(|class| !classes.contains(class))
+                .copied()
+                .collect();
+            self.require = classes.clone();
+        }
+        // Without --require all classes are required anyway
+        if self.require_mixed_case && !self.require.is_empty() {
+            for class in [Constraint::LowerCaseLetter, Constraint::UpperCaseLetter] {
+                if !self.require.contains(&class) {
+                    self.require.push(class);
+                }
+            }
+        }
+        if let Some(n) = self.target_length {
+            let jitter = self.jitter.unwrap_or(0.0);
+            self.min = (n as f64 * (1.0 - jitter)).ceil() as usize;
+            self.max = (n as f64 * (1.0 + jitter)).floor() as usize;
+        }
+        if let Some(path) = &self.seed_from_file {
+            self.file_seed = Some(file_seed(path)?);
+        }
+        if let Some(path) = &self.policy {
+            let policies = policy::load(path).map_err(GenError::Io)?;
+            let merged = policy::merge(&policies).map_err(GenError::Unsatisfiable)?;
+            self.apply_policy(merged)?;
+        }
+        Ok(())
+    }
+
+    /// Narrow the options down to what `policy` allows as well.
+    fn apply_policy(&mut self, policy: policy::Merged) -> Result<(), GenError> {
+        if let Some(class) = policy.exclude.iter().find(|c| self.require.contains(c)) {
+            return Err(GenError::Conflict(format!(
+                "--require {} contradicts the policies",
+                class.to_possible_value().unwrap().get_name()
+            )));
+        }
+        if let Some(class) = policy
+            .require
+            .iter()
+            .find(|c| self.exclude.contains(c) || self.shape_excluded().contains(c))
+        {
+            return Err(GenError::Conflict(format!(
+                "The policies require {}, which the options exclude",
+                class.to_possible_value().unwrap().get_name()
+            )));
+        }
+        self.min = self.min.max(policy.min.unwrap_or(0));
+        self.max = self.max.min(policy.max.unwrap_or(usize::MAX));
+        self.max_class_run = match (self.max_class_run, policy.max_class_run) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        // Without --require all classes are required anyway
+        if !self.require.is_empty() {
+            for class in policy.require {
+                if !self.require.contains(&class) {
+                    self.require.push(class);
+                }
+            }
+        }
+        self.exclude.extend(policy.exclude);
+        if let Some(allowed) = policy.symbols {
+            self.symbols.retain(|c| allowed.contains(c));
+            self.emoji.retain(|e| allowed.contains(e.as_str()));
+        }
+        Ok(())
+    }
+
+    /// Classes excluded by `--letters-only` or `--no-letters`.
+    fn shape_excluded(&self) -> &'static [Constraint] {
+        if self.letters_only {
+            &[Constraint::Number, Constraint::Symbol]
+        } else if self.no_letters {
+            &[Constraint::LowerCaseLetter, Constraint::UpperCaseLetter]
+        } else {
+            &[]
+        }
+    }
+}
+
+/// The options for generating passwords, identical to the command line
+/// options.
+pub type Config = Opts;
+
+/// A password that passed all constraints.
+pub struct Generated {
+    pub password: String,
+    /// How many candidates were created to find this one
+    pub tries: u32,
+    /// Estimated entropy in bits, the sum of the entropy of each random pick
+    pub entropy: f64,
+    /// The individual picks making up `password`, in order
+    pub tokens: Vec<Token>,
+    /// Why each of the candidates before this one was rejected
+    pub rejected: Vec<Rejection>,
+}
+
+/// The first constraint a rejected candidate failed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rejection {
+    /// A word or character could not be picked
+    Incomplete,
+    TooShort,
+    TooLong,
+    /// More words than `--max-words`
+    TooManyWords,
+    /// Fewer long words than `--min-long-words`
+    TooFewLongWords,
+    /// A word used again within `--no-repeat-within` words
+    RepeatedWord,
+    /// A required class is missing
+    Missing(Constraint),
+    NotAscii,
+    LeadingZero,
+    /// A symbol or lookalike at the start, see `--require-non-ambiguous-start`
+    AmbiguousStart,
+    /// Contains the `--username`
+    ContainsUsername,
+    /// A run longer than `--max-class-run`
+    ClassRun,
+    /// A `--position-constraint` does not hold
+    Position,
+    /// A run longer than `--max-consecutive-vowels`
+    VowelRun,
+    /// A run longer than `--max-consecutive-consonants`
+    ConsonantRun,
+    /// The random characters spell a word, see `--reject-random-words`
+    SpellsWord,
+    /// The `--post-filter` program rejected the candidate
+    PostFilter,
+    /// Fewer random numbers than `--min-random-digits`
+    TooFewRandomDigits,
+    /// Fewer different symbols than `--min-distinct-symbols`
+    TooFewDistinctSymbols,
+    /// Outside of a `--symbol-tier-min` or `--symbol-tier-max` bound
+    SymbolTier,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Incomplete => f.write_str("incomplete"),
+            Rejection::TooShort => f.write_str("too short"),
+            Rejection::TooLong => f.write_str("too long"),
+            Rejection::TooManyWords => f.write_str("too many words"),
+            Rejection::TooFewLongWords => f.write_str("too few long words"),
+            Rejection::RepeatedWord => f.write_str("repeated word"),
+            Rejection::Missing(class) => write!(
+                f,
+                "missing {}",
+                class.to_possible_value().unwrap().get_name()
+            ),
+            Rejection::NotAscii => f.write_str("not ascii"),
+            Rejection::LeadingZero => f.write_str("leading zero"),
+            Rejection::AmbiguousStart => f.write_str("ambiguous start"),
+            Rejection::ContainsUsername => f.write_str("contains the user name"),
+            Rejection::ClassRun => f.write_str("class run"),
+            Rejection::Position => f.write_str("position constraint"),
+            Rejection::VowelRun => f.write_str("vowel run"),
+            Rejection::ConsonantRun => f.write_str("consonant run"),
+            Rejection::SpellsWord => f.write_str("spells a word"),
+            Rejection::PostFilter => f.write_str("post filter"),
+            Rejection::TooFewRandomDigits => f.write_str("too few random digits"),
+            Rejection::TooFewDistinctSymbols => f.write_str("too few distinct symbols"),
+            Rejection::SymbolTier => f.write_str("symbol tier bound"),
+        }
+    }
+}
+
+/// A soft check `--auto-relax` may drop, in the order they are dropped.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Relaxation {
+    NoRepeatWithin,
+    LetterRuns,
+    ClassRun,
+    LongWords,
+    RandomWords,
+}
+
+impl Relaxation {
+    const ORDER: [Relaxation; 5] = [
+        Relaxation::NoRepeatWithin,
+        Relaxation::LetterRuns,
+        Relaxation::ClassRun,
+        Relaxation::LongWords,
+        Relaxation::RandomWords,
+    ];
+
+    /// The checks `opts` enables, in the order `--auto-relax` drops them.
+    fn applicable(opts: &Opts) -> Vec<Relaxation> {
+        Relaxation::ORDER
+            .into_iter()
+            .filter(|relaxation| match relaxation {
+                Relaxation::NoRepeatWithin => opts.command.repeat_window().is_some(),
+                Relaxation::LetterRuns => {
+                    opts.max_consecutive_vowels.is_some()
+                        || opts.max_consecutive_consonants.is_some()
+                }
+                Relaxation::ClassRun => opts.max_class_run.is_some(),
+                Relaxation::LongWords => opts.command.long_words().is_some(),
+                Relaxation::RandomWords => opts.reject_random_words,
+            })
+            .collect()
+    }
+
+    /// The options the check comes from, as they are reported.
+    fn options(self) -> &'static str {
+        match self {
+            Relaxation::NoRepeatWithin => "--no-repeat-within",
+            Relaxation::LetterRuns => "--max-consecutive-vowels and --max-consecutive-consonants",
+            Relaxation::ClassRun => "--max-class-run",
+            Relaxation::LongWords => "--min-long-words",
+            Relaxation::RandomWords => "--reject-random-words",
+        }
+    }
+}
+
+/// A single pick that is part of a password.
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub text: String,
+    /// The class the pick was made for
+    pub class: Constraint,
+    /// Whether `text` is a dictionary word rather than a random character
+    pub word: bool,
+    /// Entropy of the pick in bits
+    pub bits: f64,
+}
+
+impl Token {
+    /// How the pick is described by `--explain`.
+    pub fn source(&self) -> &'static str {
+        match self.class {
+            _ if self.word => "dictionary word",
+            Constraint::LowerCaseLetter => "random lower case letter",
+            Constraint::UpperCaseLetter => "random upper case letter",
+            Constraint::Number => "random number",
+            Constraint::Symbol => "random symbol",
+        }
+    }
+}
+
+/// Entropy in bits of uniformly picking one element from `n` options.
+pub fn bits_of_choice(n: usize) -> f64 {
+    (n as f64).log2()
+}
+
+/// Expected time for an attacker to find a password with `bits` of entropy
+/// when guessing at `guesses_per_sec`. On average half the space has to be
+/// searched.
+pub fn crack_time(bits: f64, guesses_per_sec: f64) -> Duration {
+    let secs = (bits - 1.0).exp2() / guesses_per_sec;
+    Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX)
+}
+
+/// Render a duration as a rough human readable estimate, e.g. "3 centuries".
+pub fn humanize_duration(d: Duration) -> String {
+    const MINUTE: f64 = 60.0;
+    const HOUR: f64 = 60.0 * MINUTE;
+    const DAY: f64 = 24.0 * HOUR;
+    const MONTH: f64 = 30.0 * DAY;
+    const YEAR: f64 = 365.0 * DAY;
+    const CENTURY: f64 = 100.0 * YEAR;
+
+    if d == Duration::MAX {
+        return "practically forever".to_string();
+    }
+    let secs = d.as_secs_f64();
+    if secs < 1.0 {
+        return "less than a second".to_string();
+    }
+    let (amount, unit) = [
+        (CENTURY, "century"),
+        (YEAR, "year"),
+        (MONTH, "month"),
+        (DAY, "day"),
+        (HOUR, "hour"),
+        (MINUTE, "minute"),
+    ]
+    .into_iter()
+    .find(|(unit_secs, _)| secs >= *unit_secs)
+    .map_or((secs, "second"), |(unit_secs, unit)| {
+        (secs / unit_secs, unit)
+    });
+    let amount = amount.floor() as u64;
+    match (amount, unit) {
+        (1, unit) => format!("1 {unit}"),
+        (n, "century") => format!("{n} centuries"),
+        (n, unit) => format!("{n} {unit}s"),
+    }
+}
+
+/// Groups of characters that look alike in many fonts, with their names.
+const LOOKALIKES: &[&[(char, &str)]] = &[
+    &[
+        ('l', "lower case L"),
+        ('1', "one"),
+        ('I', "upper case i"),
+        ('|', "vertical bar"),
+    ],
+    &[('O', "upper case o"), ('0', "zero"), ('o', "lower case o")],
+    &[('S', "upper case s"), ('5', "five")],
+    &[('Z', "upper case z"), ('2', "two")],
+    &[('B', "upper case b"), ('8', "eight")],
+];
+
+/// Characters commonly written instead of a letter, e.g. `4` for `a`, and
+/// that letter.
+const LEET: &[(char, char)] = &[
+    ('4', 'a'),
+    ('@', 'a'),
+    ('8', 'b'),
+    ('3', 'e'),
+    ('9', 'g'),
+    ('1', 'i'),
+    ('!', 'i'),
+    ('|', 'i'),
+    ('l', 'i'),
+    ('0', 'o'),
+    ('5', 's'),
+    ('$', 's'),
+    ('7', 't'),
+    ('+', 't'),
+];
+
+/// Whether `s` contains `name`, ignoring case and with `leet` also common
+/// substitutions of its letters.
+fn contains_username(s: &str, name: &str, leet: bool) -> bool {
+    let normalize = |s: &str| {
+        s.to_lowercase()
+            .chars()
+            .map(|c| match LEET.iter().find(|(from, _)| leet && *from == c) {
+                Some((_, to)) => *to,
+                None => c,
+            })
+            .collect::<String>()
+    };
+    normalize(s).contains(&normalize(name))
+}
+
+/// Whether `c` is in one of the [`LOOKALIKES`] groups.
+fn is_lookalike(c: char) -> bool {
+    LOOKALIKES
+        .iter()
+        .any(|group| group.iter().any(|(other, _)| *other == c))
+}
+
+/// Whether the first character of `s` is a letter or number without a
+/// lookalike, for `--require-non-ambiguous-start`.
+fn starts_unambiguously(opts: &Opts, s: &str) -> bool {
+    graphemes::graphemes(s).next().is_some_and(|first| {
+        first.chars().count() == 1
+            && !first.chars().any(is_lookalike)
+            && Constraint::classify(opts, first).is_some_and(|class| class != Constraint::Symbol)
+    })
+}
+
+/// Characters that take more than a single key press on a layout, and what
+/// else is needed. Upper case letters are left out, they need Shift
+/// everywhere.
+const KEYBOARD_DIFFICULTIES: &[(KeyboardLayout, &str, &str)] = &[
+    (KeyboardLayout::Us, "~!@#$%^&*()_+{}|:\"<>?", "Shift"),
+    (KeyboardLayout::Azerty, "1234567890°+£µ%?./§>", "Shift"),
+    (KeyboardLayout::Azerty, "#{[|\\@]}€", "AltGr"),
+    (KeyboardLayout::Azerty, "^¨~`", "a dead key"),
+    (KeyboardLayout::Qwertz, "!\"§$%&/()=?'*>;:_°", "Shift"),
+    (KeyboardLayout::Qwertz, "@€{[]}\\~|µ²³", "AltGr"),
+    (KeyboardLayout::Qwertz, "^´`", "a dead key"),
+];
+
+/// One line per kind of awkward key in `password` on `layout`, listing the
+/// characters that need it.
+pub fn keyboard_warnings(layout: KeyboardLayout, password: &str) -> Vec<String> {
+    let name = layout.to_possible_value().unwrap().get_name().to_string();
+    KEYBOARD_DIFFICULTIES
+        .iter()
+        .filter(|(l, _, _)| *l == layout)
+        .filter_map(|(_, chars, how)| {
+            let found = chars
+                .chars()
+                .filter(|c| password.contains(*c))
+                .map(String::from)
+                .collect::<Vec<_>>();
+            let verb = if found.len() == 1 { "needs" } else { "need" };
+            (!found.is_empty()).then(|| format!("{} {verb} {how} on {name}", found.join(" ")))
+        })
+        .collect()
+}
+
+/// One line per lookalike character that occurs in `password`, naming it and
+/// the characters it could be mistaken for.
+pub fn lookalike_legend(password: &str) -> Vec<String> {
+    LOOKALIKES
+        .iter()
+        .flat_map(|group| {
+            group
+                .iter()
+                .filter(|(c, _)| password.contains(*c))
+                .map(move |(c, name)| {
+                    let others = group
+                        .iter()
+                        .filter(|(other, _)| other != c)
+                        .map(|(other, name)| format!("{name} ({other})"))
+                        .collect::<Vec<_>>();
+                    format!("contains {name} ({c}), not {}", others.join(" or "))
+                })
+        })
+        .collect()
+}
+
+/// Appends a letter or word of the requested case (lower case if the flag is
+/// set) to the candidate and returns the entropy of that pick, or `None` if
+/// nothing fits anymore.
+type PickLetters<'a> = dyn Fn(&mut String, &mut dyn RngCore, bool) -> Option<f64> + 'a;
+
+/// PBKDF2 iterations for `derive`. Changing this changes all derived
+/// passwords.
+#[cfg(feature = "kdf")]
+const DERIVE_ITERATIONS: u32 = 100_000;
+
+/// The seed `--seed-from-file` makes of the file at `path`.
+#[cfg(feature = "kdf")]
+fn file_seed(path: &std::path::Path) -> Result<[u8; 32], GenError> {
+    let material = std::fs::read(path)
+        .map_err(|e| GenError::Io(format!("Could not read seed file {}: {e}", path.display())))?;
+    Ok(kdf::sha256(&[b"gen-pw seed file\0", &material]))
+}
+
+#[cfg(not(feature = "kdf"))]
+fn file_seed(_: &std::path::Path) -> Result<[u8; 32], GenError> {
+    Err(GenError::Conflict(
+        "gen-pw was built without the kdf feature, which --seed-from-file needs".to_string(),
+    ))
+}
+
+/// Seed for the RNG of the `derive` command.
+#[cfg(feature = "kdf")]
+fn derive_seed(master: &str, site: &str, revision: u32) -> [u8; 32] {
+    let mut salt = [b"gen-pw derive\0", site.as_bytes()].concat();
+    // Revision 0 keeps the salt of passwords derived before revisions existed
+    if revision > 0 {
+        salt.push(0);
+        salt.extend_from_slice(&revision.to_be_bytes());
+    }
+    kdf::pbkdf2_sha256(master.as_bytes(), &salt, DERIVE_ITERATIONS)
+}
+
+/// [`validate`] rejects `derive` without the KDF.
+#[cfg(not(feature = "kdf"))]
+fn derive_seed(_: &str, _: &str, _: u32) -> [u8; 32] {
+    panic!("gen-pw was built without the kdf feature, which derive needs")
+}
+
+/// Length of the longest run of consecutive characters of the same class.
+/// Characters in no class form runs of their own.
+fn longest_class_run(opts: &Opts, s: &str) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    let mut previous = None;
+    for grapheme in graphemes::graphemes(s) {
+        let class = Constraint::classify(opts, grapheme);
+        current = if current > 0 && previous == Some(class) {
+            current + 1
+        } else {
+            1
+        };
+        previous = Some(class);
+        longest = longest.max(current);
+    }
+    longest
+}
+
+/// The number of different symbols in `s`.
+fn distinct_symbols(opts: &Opts, s: &str) -> usize {
+    graphemes::graphemes(s)
+        .filter(|g| Constraint::classify(opts, g) == Some(Constraint::Symbol))
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+/// The length of the longest run of vowels, or of consonants if `vowels` is
+/// false, in `s`. Only ASCII letters count, other characters end a run.
+fn longest_letter_run(s: &str, vowels: bool) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    for c in s.chars() {
+        let is_vowel = matches!(c.to_ascii_lowercase(), 'a' | 'e' | 'i' | 'o' | 'u');
+        current = if c.is_ascii_alphabetic() && is_vowel == vowels {
+            current + 1
+        } else {
+            0
+        };
+        longest = longest.max(current);
+    }
+    longest
+}
+
+/// Rough estimate of the probability that a candidate of the legacy
+/// algorithm contains every required class, assuming one class pick per
+/// character of a password of length `min` (inclusion-exclusion over the
+/// sets of missing classes).
+fn class_coverage_probability(opts: &Opts, required: &[Constraint]) -> f64 {
+    let weights = class_weights(opts, required);
+    let total = weights.iter().sum::<f64>();
+    (0u32..1 << weights.len())
+        .map(|missing| {
+            let missing_weight = weights
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| missing & (1 << i) != 0)
+                .map(|(_, w)| w)
+                .sum::<f64>();
+            let sign = if missing.count_ones() % 2 == 0 {
+                1.0
+            } else {
+                -1.0
+            };
+            sign * (1.0 - missing_weight / total).powi(opts.min as i32)
+        })
+        .sum()
+}
+
+/// Relative probability of picking each of the `required` classes for a
+/// position, taking `--symbol-probability` and `--number-probability` into
+/// account.
+fn class_weights(opts: &Opts, required: &[Constraint]) -> Vec<f64> {
+    let fixed = |class| match class {
+        Constraint::Symbol => opts.symbol_probability,
+        Constraint::Number => opts.number_probability,
+        Constraint::LowerCaseLetter | Constraint::UpperCaseLetter => None,
+    };
+    let fixed_total = required.iter().filter_map(|c| fixed(*c)).sum::<f64>();
+    let free_total = required
+        .iter()
+        .filter(|c| fixed(**c).is_none())
+        .map(|c| opts.command.weights(*c) as f64)
+        .sum::<f64>();
+    required
+        .iter()
+        .map(|c| {
+            fixed(*c).unwrap_or_else(|| {
+                (1.0 - fixed_total) * opts.command.weights(*c) as f64 / free_total
+            })
+        })
+        .collect()
+}
+
+fn parse_probability(s: &str) -> Result<f64, String> {
+    let p = s.parse::<f64>().map_err(|e| e.to_string())?;
+    if (0.0..=1.0).contains(&p) {
+        Ok(p)
+    } else {
+        Err(format!("{p} is not between 0 and 1"))
+    }
+}
+
+/// Whether some combination of words with the given lengths, together with
+/// the mandatory numbers and symbols, fits the length range. Combinations of
+/// up to [`FEASIBILITY_MAX_WORDS`] words (or the minimum number of words, if
+/// larger) are considered.
+fn dict_length_feasible(opts: &Opts, required: &[Constraint], word_lengths: &[usize]) -> bool {
+    let letter_classes = required
+        .iter()
+        .filter(|c| matches!(c, Constraint::LowerCaseLetter | Constraint::UpperCaseLetter))
+        .count();
+    if letter_classes == 0 {
+        return true;
+    }
+    let single_classes = required.len() - letter_classes;
+    let (min_words, max_words) = opts.command.word_band();
+    let (fewest_words, singles) = if opts.legacy_gen {
+        (min_words.max(1), 0)
+    } else {
+        (min_words.max(letter_classes), single_classes)
+    };
+    let most_words = max_words
+        .unwrap_or(usize::MAX)
+        .min(fewest_words.max(FEASIBILITY_MAX_WORDS));
+    let min = if opts.allow_shorter_than_min_if_exhausted {
+        0
+    } else {
+        opts.min
+    };
+    // Whether some combination of the current number of words has a total
+    // length of the index
+    let mut reachable = vec![false; opts.max + 1];
+    reachable[0] = true;
+    for words in 1..=most_words {
+        let mut next = vec![false; opts.max + 1];
+        for (len, _) in reachable.iter().enumerate().filter(|(_, r)| **r) {
+            for word_len in word_lengths {
+                if let Some(r) = next.get_mut(len + word_len) {
+                    *r = true;
+                }
+            }
+        }
+        reachable = next;
+        let fits = reachable
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| **r)
+            .map(|(len, _)| len + singles)
+            // Numbers and symbols can pad the password up to `min`
+            .any(|len| len <= opts.max && (single_classes > 0 || len >= min));
+        if words >= fewest_words && fits {
+            return true;
+        }
+    }
+    false
+}
+
+/// Checks beyond length and required classes that a candidate must pass.
+pub(crate) fn passes_secondary_checks(opts: &Opts, s: &str) -> bool {
+    secondary_rejection(opts, s, &[]).is_none()
+}
+
+/// The first secondary check `s` fails, if any, skipping the `dropped` ones.
+fn secondary_rejection(opts: &Opts, s: &str, dropped: &[Relaxation]) -> Option<Rejection> {
+    if opts.ascii_only && !s.is_ascii() {
+        Some(Rejection::NotAscii)
+    } else if opts.no_leading_zero && s.starts_with('0') {
+        Some(Rejection::LeadingZero)
+    } else if opts.require_non_ambiguous_start && !starts_unambiguously(opts, s) {
+        Some(Rejection::AmbiguousStart)
+    } else if opts
+        .username
+        .as_deref()
+        .is_some_and(|name| contains_username(s, name, opts.username_leet))
+    {
+        Some(Rejection::ContainsUsername)
+    } else if !dropped.contains(&Relaxation::ClassRun)
+        && opts
+            .max_class_run
+            .is_some_and(|n| longest_class_run(opts, s) > n)
+    {
+        Some(Rejection::ClassRun)
+    } else if !dropped.contains(&Relaxation::LetterRuns)
+        && opts
+            .max_consecutive_vowels
+            .is_some_and(|n| longest_letter_run(s, true) > n)
+    {
+        Some(Rejection::VowelRun)
+    } else if !dropped.contains(&Relaxation::LetterRuns)
+        && opts
+            .max_consecutive_consonants
+            .is_some_and(|n| longest_letter_run(s, false) > n)
+    {
+        Some(Rejection::ConsonantRun)
+    } else if !opts
+        .position_constraint
+        .iter()
+        .all(|constraint| constraint.verify(opts, s))
+    {
+        Some(Rejection::Position)
+    } else {
+        None
+    }
+}
+
+/// Whether `s` has as many symbols of each `--symbol-tier` as the bounds
+/// allow.
+fn symbol_tiers_hold(opts: &Opts, s: &str) -> bool {
+    let count = |name: &str| {
+        opts.symbol_tier
+            .iter()
+            .find(|tier| tier.name == name)
+            .map_or(0, |tier| tier.count(s))
+    };
+    opts.symbol_tier_min
+        .iter()
+        .all(|bound| count(&bound.name) >= bound.count)
+        && opts
+            .symbol_tier_max
+            .iter()
+            .all(|bound| count(&bound.name) <= bound.count)
+}
+
+/// Whether a dictionary word of `tokens` occurs again within the next `k`
+/// words. Case does not matter, `--title-case` changes it.
+fn repeats_within(tokens: &[Token], k: usize) -> bool {
+    let words = tokens
+        .iter()
+        .filter(|token| token.word)
+        .map(|token| token.text.to_lowercase())
+        .collect::<Vec<_>>();
+    words
+        .iter()
+        .enumerate()
+        .any(|(i, word)| words[i + 1..].iter().take(k).any(|w| w == word))
+}
+
+/// The elements of `pool` that are not `used` yet with
+/// `--maximize-diversity`, all of them otherwise or if every one was used.
+fn unused<'p, T>(opts: &Opts, pool: &'p [T], used: impl Fn(&T) -> bool) -> Vec<&'p T> {
+    let fresh = pool
+        .iter()
+        .filter(|x| !opts.maximize_diversity || !used(x))
+        .collect::<Vec<_>>();
+    if fresh.is_empty() {
+        pool.iter().collect()
+    } else {
+        fresh
+    }
+}
+
+/// An endless stream of independently generated passwords. Letters are
+/// picked with `pick_letters`, the words of `mixed` with `pick_words`.
+/// Candidates whose random characters spell a word in `avoid` are rejected.
+fn generate_iter<'a>(
+    opts: &'a Opts,
+    required: &'a [Constraint],
+    pick_letters: Box<PickLetters<'a>>,
+    pick_words: Option<Box<PickLetters<'a>>>,
+    avoid: HashSet<String>,
+    rng: &'a mut dyn RngCore,
+) -> impl Iterator<Item = Result<Generated, GenError>> + 'a {
+    // Checks dropped by `--auto-relax` stay dropped for later passwords
+    let mut relaxed = 0;
+    std::iter::repeat_with(move || {
+        do_gen(
+            opts,
+            required,
+            &*pick_letters,
+            pick_words.as_deref(),
+            &avoid,
+            &mut relaxed,
+            rng,
+        )
+    })
+}
+
+/// `s` cut to at most `max` characters, with an ellipsis if it was longer.
+fn abbreviate(s: &str, max: usize) -> String {
+    let prefix = graphemes::truncate_graphemes(s, max);
+    if prefix.len() < s.len() {
+        format!("{prefix}…")
+    } else {
+        s.to_string()
+    }
+}
+
+/// Whether `s` contains one of the lower case words in `avoid`, ignoring
+/// case.
+fn spells_word(avoid: &HashSet<String>, s: &str) -> bool {
+    let s = s.to_lowercase();
+    let starts = s.char_indices().map(|(i, _)| i).collect::<Vec<_>>();
+    starts.iter().enumerate().any(|(n, &start)| {
+        starts[n + RANDOM_WORD_MIN_LEN.min(starts.len() - n)..]
+            .iter()
+            .copied()
+            .chain(std::iter::once(s.len()))
+            .any(|end| avoid.contains(&s[start..end]))
+    })
+}
+
+fn do_gen(
+    opts: &Opts,
+    required: &[Constraint],
+    pick_letters: &PickLetters,
+    pick_words: Option<&PickLetters>,
+    avoid: &HashSet<String>,
+    relaxed: &mut usize,
+    rng: &mut dyn RngCore,
+) -> Result<Generated, GenError> {
+    let weights = class_weights(opts, required);
+    let dist = rand::distributions::WeightedIndex::new(&weights).unwrap();
+    let symbol_pool = opts
+        .symbols
+        .chars()
+        .map(String::from)
+        .chain(opts.emoji.iter().cloned())
+        .collect::<Vec<_>>();
+    let number_pool = opts.number_chars.chars().collect::<Vec<_>>();
+    // Longest candidate that fell short of `min` but satisfies everything
+    // else, for `--allow-shorter-than-min-if-exhausted`
+    let mut best_effort: Option<(String, f64, Vec<Token>)> = None;
+    let mut rejected = vec![];
+    let pick = |class, s: &mut String, rng: &mut dyn RngCore| match class {
+        Constraint::LowerCaseLetter => pick_letters(s, rng, true),
+        Constraint::UpperCaseLetter => pick_letters(s, rng, false),
+        Constraint::Symbol => {
+            let pool = unused(opts, &symbol_pool, |e| s.contains(e.as_str()));
+            s.push_str(pool.choose(rng).unwrap());
+            Some(bits_of_choice(pool.len()))
+        }
+        Constraint::Number => {
+            let pool = unused(opts, &number_pool, |c| s.contains(*c));
+            s.push(**pool.choose(rng).unwrap());
+            Some(bits_of_choice(pool.len()))
+        }
+    };
+    let (min_words, max_words) = opts.command.word_band();
+    let pad_with_chars = matches!(
+        opts.command,
+        Command::Dict {
+            pad_with_chars: true,
+            ..
+        }
+    );
+    let pad_letters = chars_picker(opts);
+    // The filter only sees candidates that pass everything else
+    let post_filter_accepts = |s: &str| match &opts.post_filter {
+        Some(program) => filter::accepts(program, s).map_err(GenError::Io),
+        None => Ok(true),
+    };
+    let is_word = |class| {
+        matches!(opts.command, Command::Dict { .. })
+            && matches!(
+                class,
+                Constraint::LowerCaseLetter | Constraint::UpperCaseLetter
+            )
+    };
+    let word_classes = required
+        .iter()
+        .copied()
+        .filter(|c| is_word(*c))
+        .collect::<Vec<_>>();
+    let letter_classes = required
+        .iter()
+        .copied()
+        .filter(|c| matches!(c, Constraint::LowerCaseLetter | Constraint::UpperCaseLetter))
+        .collect::<Vec<_>>();
+    // Whether `--maximize-diversity` has to repeat a character of `class`
+    let exhausted = |class, s: &str| {
+        let pool = match class {
+            _ if is_word(class) => return false,
+            Constraint::LowerCaseLetter => &opts.lower_chars,
+            Constraint::UpperCaseLetter => &opts.upper_chars,
+            Constraint::Number => &opts.number_chars,
+            Constraint::Symbol => {
+                return opts.symbols.chars().all(|c| s.contains(c))
+                    && opts.emoji.iter().all(|e| s.contains(e.as_str()))
+            }
+        };
+        pool.chars().all(|c| s.contains(c))
+    };
+    // `--auto-relax` drops the first `relaxed` of these, one more whenever a
+    // round of `--tries` candidates fails
+    let relaxations = if opts.auto_relax {
+        Relaxation::applicable(opts)
+    } else {
+        vec![]
+    };
+    let rounds = 1 + relaxations.len() - *relaxed;
+    // Every round of `--auto-relax` gets its own `--tries`
+    let tries = opts.tries * rounds;
+    let mut round_tries = 0;
+    let found = std::iter::repeat_with(|| {
+        if round_tries == opts.tries {
+            warn!(
+                "No candidate passed in {} tries, dropping {}",
+                opts.tries,
+                relaxations[*relaxed].options()
+            );
+            *relaxed += 1;
+            round_tries = 0;
+        }
+        round_tries += 1;
+        let dropped = &relaxations[..*relaxed];
+        let mut s = String::new();
+        let mut entropy = 0.0;
+        let mut complete = true;
+        // Classes and byte ranges of the individual picks in `s`
+        let mut tokens = vec![];
+        // The classes of `s`, updated with every pick so the judgement does not
+        // have to scan long candidates again
+        let mut present = ClassSet::default();
+        // The constructive algorithm starts with one pick of each required
+        // class so that the class constraints hold by construction, the
+        // legacy one relies on chance and rejection alone. The other
+        // `--classes-order`s decide the class of every position themselves.
+        let constructive = !opts.legacy_gen && opts.classes_order == ClassesOrder::Shuffle;
+        let mut mandatory = if constructive {
+            required.to_vec()
+        } else {
+            vec![]
+        };
+        if constructive {
+            let minimums = [
+                (Constraint::Number, opts.min_random_digits),
+                (Constraint::Symbol, opts.min_distinct_symbols),
+            ];
+            for (class, n) in minimums {
+                let picks = mandatory.iter().filter(|c| **c == class).count();
+                let missing = n.unwrap_or(0).saturating_sub(picks);
+                mandatory.extend(std::iter::repeat_n(class, missing));
+            }
+        }
+        let mut words = 0;
+        // In mixed mode the words come first and only the random characters
+        // after them are shuffled
+        let mut fixed_prefix = 0;
+        if let (Command::Mixed { word_count, .. }, Some(pick_words)) = (&opts.command, pick_words) {
+            for _ in 0..*word_count {
+                let class = match mandatory.iter().position(|c| letter_classes.contains(c)) {
+                    Some(i) => mandatory.remove(i),
+                    None => *letter_classes.choose(rng).unwrap(),
+                };
+                let start = s.len();
+                match pick_words(&mut s, rng, class == Constraint::LowerCaseLetter) {
+                    Some(bits) => {
+                        entropy += bits;
+                        present.extend(ClassSet::of(opts, &s[start..]));
+                        tokens.push((class, true, start..s.len(), bits));
+                        words += 1;
+                    }
+                    None => complete = false,
+                }
+            }
+            fixed_prefix = tokens.len();
+        }
+        let mut random_picks = 0;
+        while complete {
+            let long_enough = match &opts.command {
+                Command::Mixed { random_chars, .. } => random_picks >= *random_chars,
+                _ => grapheme_len(&s) >= opts.min,
+            };
+            random_picks += 1;
+            let class = match mandatory.pop() {
+                Some(class) => class,
+                None if !long_enough && opts.classes_order == ClassesOrder::RoundRobin => {
+                    required[(random_picks - 1) % required.len()]
+                }
+                None if !long_enough => {
+                    let class = required[dist.sample(rng)];
+                    if opts.maximize_diversity && exhausted(class, &s) {
+                        // Prefer a class that still has unused characters
+                        required
+                            .iter()
+                            .zip(&weights)
+                            .filter(|(c, _)| !exhausted(**c, &s))
+                            .collect::<Vec<_>>()
+                            .choose_weighted(rng, |(_, w)| **w)
+                            .map_or(class, |(c, _)| **c)
+                    } else {
+                        class
+                    }
+                }
+                // Only the word count is missing
+                None if words < min_words => *word_classes.choose(rng).unwrap(),
+                None => break,
+            };
+            if is_word(class) {
+                words += 1;
+            }
+            let start = s.len();
+            match pick(class, &mut s, rng) {
+                Some(bits) => {
+                    entropy += bits;
+                    present.extend(ClassSet::of(opts, &s[start..]));
+                    tokens.push((class, is_word(class), start..s.len(), bits));
+                }
+                None if is_word(class)
+                    && pad_with_chars
+                    // Mandatory picks can already have gone past `max`
+                    && grapheme_len(&s) <= opts.max
+                    && opts.max - grapheme_len(&s) <= PAD_MAX_CHARS =>
+                {
+                    // No word fits the last few characters, use a letter
+                    words -= 1;
+                    match pad_letters(&mut s, rng, class == Constraint::LowerCaseLetter) {
+                        Some(bits) => {
+                            entropy += bits;
+                            present.extend(ClassSet::of(opts, &s[start..]));
+                            tokens.push((class, false, start..s.len(), bits));
+                        }
+                        None => {
+                            complete = false;
+                            break;
+                        }
+                    }
+                }
+                None => {
+                    complete = false;
+                    break;
+                }
+            }
+        }
+        if constructive {
+            // Move the mandatory picks to random positions
+            tokens[fixed_prefix..].shuffle(rng);
+            let mut shuffled = String::with_capacity(s.len());
+            for (_, _, range, _) in &mut tokens {
+                let start = shuffled.len();
+                shuffled.push_str(&s[range.clone()]);
+                *range = start..shuffled.len();
+            }
+            s = shuffled;
+        }
+        if let Some(dict) = opts.command.dict_args().filter(|dict| dict.title_case) {
+            let locale = dict.casing_locale;
+            let mut recased = String::with_capacity(s.len());
+            let mut first = true;
+            for (class, word, range, _) in &mut tokens {
+                let start = recased.len();
+                if *word {
+                    let mut chars = s[range.clone()].chars();
+                    if let Some(c) = chars.next() {
+                        if first {
+                            recased.extend(to_upper(c, locale));
+                        } else {
+                            recased.extend(to_lower(c, locale));
+                        }
+                    }
+                    recased.extend(chars.flat_map(|c| to_lower(c, locale)));
+                    *class = if first {
+                        Constraint::UpperCaseLetter
+                    } else {
+                        Constraint::LowerCaseLetter
+                    };
+                    first = false;
+                } else {
+                    recased.push_str(&s[range.clone()]);
+                }
+                *range = start..recased.len();
+            }
+            s = recased;
+            // Changing the case changes the letter classes
+            present = ClassSet::of(opts, &s);
+        }
+        let tokens = tokens
+            .into_iter()
+            .map(|(class, word, range, bits)| Token {
+                text: s[range].to_string(),
+                class,
+                word,
+                bits,
+            })
+            .collect::<Vec<_>>();
+        let len = grapheme_len(&s);
+        // Mixed passwords have a fixed number of picks instead of growing
+        // until they reach `min`
+        let long_enough = pick_words.is_none() || len >= opts.min;
+        debug_assert_eq!(present, ClassSet::of(opts, &s), "class tracking of {s}");
+        let constraint_rejection = if len > opts.max {
+            Some(Rejection::TooLong)
+        } else if max_words.is_some_and(|n| words > n) {
+            Some(Rejection::TooManyWords)
+        } else if !dropped.contains(&Relaxation::LongWords)
+            && opts.command.long_words().is_some_and(|(n, threshold)| {
+                tokens
+                    .iter()
+                    .filter(|token| token.word && grapheme_len(&token.text) >= threshold)
+                    .count()
+                    < n
+            })
+        {
+            Some(Rejection::TooFewLongWords)
+        } else if !dropped.contains(&Relaxation::NoRepeatWithin)
+            && opts
+                .command
+                .repeat_window()
+                .is_some_and(|k| repeats_within(&tokens, k))
+        {
+            Some(Rejection::RepeatedWord)
+        } else if opts.min_random_digits.is_some_and(|n| {
+            // Digits inside dictionary words are not random picks
+            tokens
+                .iter()
+                .filter(|token| token.class == Constraint::Number && !token.word)
+                .count()
+                < n
+        }) {
+            Some(Rejection::TooFewRandomDigits)
+        } else if opts
+            .min_distinct_symbols
+            .is_some_and(|n| distinct_symbols(opts, &s) < n)
+        {
+            Some(Rejection::TooFewDistinctSymbols)
+        } else if !symbol_tiers_hold(opts, &s) {
+            Some(Rejection::SymbolTier)
+        } else if let Some(class) = required.iter().find(|c| !present.contains(**c)) {
+            Some(Rejection::Missing(*class))
+        } else if let Some(rejection) = secondary_rejection(opts, &s, dropped) {
+            Some(rejection)
+        } else if !dropped.contains(&Relaxation::RandomWords)
+            && !avoid.is_empty()
+            && spells_word(
+                avoid,
+                &tokens
+                    .iter()
+                    .filter(|token| !token.word)
+                    .map(|token| token.text.as_str())
+                    .collect::<String>(),
+            )
+        {
+            Some(Rejection::SpellsWord)
+        } else {
+            None
+        };
+        let satisfies_constraints = constraint_rejection.is_none();
+        let rejection = constraint_rejection.or(if !complete {
+            Some(Rejection::Incomplete)
+        } else if !long_enough {
+            Some(Rejection::TooShort)
+        } else if !post_filter_accepts(&s)? {
+            Some(Rejection::PostFilter)
+        } else {
+            None
+        });
+        let judgement = rejection.is_none();
+        if let Some(rejection) = rejection {
+            rejected.push(rejection);
+            debug!(
+                "Rejecting {}: {} {:?} {} {} and {:?}",
+                abbreviate(&s, DEBUG_CANDIDATE_LEN),
+                len,
+                len.cmp(&opts.max),
+                opts.max,
+                if complete { "complete" } else { "incomplete" },
+                required
+                    .iter()
+                    .map(|c| (c, present.contains(*c)))
+                    .collect::<Vec<_>>()
+            );
+            // A shorter password is still subject to the filter
+            if opts.allow_shorter_than_min_if_exhausted
+                && satisfies_constraints
+                && rejection != Rejection::PostFilter
+                && best_effort
+                    .as_ref()
+                    .is_none_or(|(best, _, _)| grapheme_len(best) < len)
+                && post_filter_accepts(&s)?
+            {
+                best_effort = Some((s.clone(), entropy, tokens.clone()));
+            }
+        }
+        Ok(judgement.then_some((s, entropy, tokens)))
+    })
+    .take(tries)
+    .enumerate()
+    // The accepted candidate counts as a try as well
+    .find_map(|(i, s)| {
+        s.map(|found| found.map(|found| (i as u32 + 1, found)))
+            .transpose()
+    })
+    .transpose()?;
+    let (tries, (password, entropy, tokens)) = found
+        .or_else(|| {
+            let best = best_effort?;
+            warn!(
+                "Could not find a satisfactory string in {tries} tries, returning a shorter one of length {}",
+                grapheme_len(&best.0)
+            );
+            Some((tries as u32, best))
+        })
+        .ok_or_else(|| GenError::Exhausted {
+            tries,
+            reason: count_rejections(rejected.iter().copied())
+                .first()
+                .map(|(reason, _)| *reason),
+        })?;
+    Ok(Generated {
+        password,
+        tries,
+        entropy,
+        tokens,
+        rejected,
+    })
+}
+
+/// Picks random letters from the `--lower-chars` and `--upper-chars` pools.
+fn chars_picker(opts: &Opts) -> Box<PickLetters<'_>> {
+    let lower = opts.lower_chars.chars().collect::<Vec<_>>();
+    let upper = opts.upper_chars.chars().collect::<Vec<_>>();
+    Box::new(move |s: &mut String, rng: &mut dyn RngCore, is_lowercase| {
+        let pool = if is_lowercase { &lower } else { &upper };
+        let pool = unused(opts, pool, |c| s.contains(*c));
+        s.push(**pool.choose(rng)?);
+        Some(bits_of_choice(pool.len()))
+    })
+}
+
+/// The usable words of `dictionary`, which was loaded from `source`, for the
+/// `dict` options.
+fn dict_words(
+    opts: &Opts,
+    dict: &DictArgs,
+    source: &str,
+    dictionary: Vec<String>,
+) -> Result<Vec<String>, GenError> {
+    if dictionary.is_empty() {
+        return Err(GenError::Unsatisfiable(format!(
+            "{} has no words",
+            capitalize(source)
+        )));
+    }
+    let mut words = dictionary
+        .into_iter()
+        .map(|word| {
+            dict.dict_preprocess
+                .iter()
+                .fold(word, |word, step| match step {
+                    DictPreprocess::Lowercase => word
+                        .chars()
+                        .flat_map(|c| to_lower(c, dict.casing_locale))
+                        .collect(),
+                    DictPreprocess::StripAccents => words::strip_accents(&word),
+                })
+        })
+        .filter(|s| {
+            !s.contains(char::REPLACEMENT_CHARACTER)
+                && (dict.keep_apostrophes || !s.contains('\''))
+                && !s.is_empty()
+                && grapheme_len(s) <= opts.max
+        })
+        .collect::<Vec<_>>();
+    if !dict.dict_preprocess.is_empty() {
+        // Words that became equal would otherwise be picked more often
+        words.sort_unstable();
+        words.dedup();
+    }
+    if words.is_empty() {
+        return Err(GenError::Unsatisfiable(format!(
+            "{} has no words of at most {} characters, raise --max",
+            capitalize(source),
+            opts.max
+        )));
+    }
+    if let Some(threshold) = dict.min_per_word_entropy {
+        let bits = bits_of_choice(words.len());
+        if bits < threshold {
+            return Err(GenError::Unsatisfiable(format!(
+                "A word of {source} only adds {bits:.1} bits, less than --min-per-word-entropy \
+                 {threshold}, it has {} usable words",
+                words.len()
+            )));
+        }
+    }
+    Ok(words)
+}
+
+/// Make sure the case policy can produce every required letter class from
+/// `words`. Each word provides the letter class it was picked for through
+/// the case of its first letter, so single-case lists work, but words
+/// starting with digits or letters outside of the pools do not.
+fn check_word_cases(
+    opts: &Opts,
+    dict: &DictArgs,
+    required: &[Constraint],
+    source: &str,
+    words: &[String],
+) -> Result<(), GenError> {
+    let first_letters = words.iter().filter_map(|word| word.chars().next());
+    for class in [Constraint::LowerCaseLetter, Constraint::UpperCaseLetter] {
+        if !required.contains(&class) {
+            continue;
+        }
+        let fits = first_letters.clone().any(|c| {
+            let (mut recased, pool) = if class == Constraint::LowerCaseLetter {
+                (
+                    Either::Left(to_lower(c, dict.casing_locale)),
+                    &opts.lower_chars,
+                )
+            } else {
+                (
+                    Either::Right(to_upper(c, dict.casing_locale)),
+                    &opts.upper_chars,
+                )
+            };
+            recased.all(|c| pool.contains(c))
+        });
+        if !fits {
+            return Err(GenError::Unsatisfiable(format!(
+                "No word of {source} starts with a letter that becomes a {} when its case is \
+                 changed",
+                class.to_possible_value().unwrap().get_name()
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .into_iter()
+        .flat_map(char::to_uppercase)
+        .chain(chars)
+        .collect()
+}
+
+/// Picks a transformed word from `words` that still fits into `--max`. The
+/// words are bucketed by length, so the pick is made among the fitting words
+/// only. In `dict` a word that completes the password is preferred the closer
+/// it gets to the middle of the length range, and one that leaves too little
+/// room for another word below `--min` is avoided, which saves many
+/// abandoned candidates. The entropy is the one of this weighted choice.
+fn dict_picker<'a>(opts: &'a Opts, dict: &'a DictArgs, words: Vec<String>) -> Box<PickLetters<'a>> {
+    let locale = dict.casing_locale;
+    // Words whose transformation does not depend on chance are transformed
+    // once, so they are bucketed by their final length
+    let mut words = words
+        .into_iter()
+        .map(|word| dict.word_transform.apply_fixed(&word).unwrap_or(word))
+        .filter(|word| !word.is_empty())
+        .map(|word| (grapheme_len(&word), word))
+        .collect::<Vec<_>>();
+    words.sort_unstable();
+    words.dedup();
+    let buckets = words
+        .chunk_by(|(a, _), (b, _)| a == b)
+        .map(|bucket| {
+            (
+                bucket[0].0,
+                bucket
+                    .iter()
+                    .map(|(_, word)| word.clone())
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let shortest = buckets.first().map_or(0, |(len, _)| *len);
+    let (is_dict, pad_with_chars) = match opts.command {
+        Command::Dict { pad_with_chars, .. } => (true, pad_with_chars),
+        _ => (false, false),
+    };
+    let middle = (opts.min + opts.max) as f64 / 2.0;
+    let half_range = ((opts.max - opts.min) as f64 / 2.0).max(1.0);
+    Box::new(move |s: &mut String, rng: &mut dyn RngCore, is_lowercase| {
+        let len = grapheme_len(s);
+        let fitting = buckets
+            .iter()
+            .take_while(|(word_len, _)| len + word_len <= opts.max)
+            .collect::<Vec<_>>();
+        // The weight of each word of a bucket
+        let preferred = fitting
+            .iter()
+            .map(|(word_len, _)| {
+                let total = len + word_len;
+                if !is_dict {
+                    1.0
+                } else if total >= opts.min {
+                    (2.0 - (total as f64 - middle).abs() / half_range).max(1.0)
+                } else if !pad_with_chars && opts.max - total < shortest {
+                    0.0
+                } else {
+                    1.0
+                }
+            })
+            .collect::<Vec<_>>();
+        let weights = if preferred.iter().any(|w| *w > 0.0) {
+            preferred
+        } else {
+            vec![1.0; fitting.len()]
+        };
+        let bucket_weights = fitting
+            .iter()
+            .zip(&weights)
+            .map(|((_, words), w)| words.len() as f64 * w)
+            .collect::<Vec<_>>();
+        let bucket = rand::distributions::WeightedIndex::new(&bucket_weights)
+            .ok()?
+            .sample(rng);
+        let total = bucket_weights.iter().sum::<f64>();
+        let word_bits = fitting
+            .iter()
+            .zip(&weights)
+            .filter(|(_, w)| **w > 0.0)
+            .map(|((_, words), w)| {
+                let p = w / total;
+                -(words.len() as f64) * p * p.log2()
+            })
+            .sum::<f64>();
+        let bucket = &fitting[bucket].1;
+        let (word, transform_bits) = if dict.word_transform == WordTransform::RandomCase {
+            (0..WORD_PICK_ATTEMPTS).find_map(|_| {
+                let (word, bits) = dict.word_transform.apply(bucket.choose(rng)?, locale, rng);
+                // Changing the case rarely changes the length
+                (grapheme_len(&word) + len <= opts.max).then_some((word, bits))
+            })?
+        } else {
+            // Already transformed
+            (bucket.choose(rng)?.clone(), 0.0)
+        };
+        let class = if is_lowercase {
+            Constraint::LowerCaseLetter
+        } else {
+            Constraint::UpperCaseLetter
+        };
+        if dict.preserve_case && word.chars().any(|c| class.contains(opts, c)) {
+            s.push_str(&word);
+            return Some(word_bits + transform_bits);
+        }
+        let mut chars = word.chars();
+        let first = chars.next().unwrap();
+
+        s.extend(
+            if is_lowercase {
+                Either::Left(to_lower(first, locale))
+            } else {
+                Either::Right(to_upper(first, locale))
+            }
+            .chain(chars),
+        );
+        Some(word_bits + transform_bits)
+    })
+}
+
+/// The source of dictionary words selected by `--dict-source`, or `None` for
+/// commands that do not use a dictionary.
+pub fn word_source(opts: &Config) -> Option<Box<dyn WordSource>> {
+    let language = match &opts.command {
+        Command::Compact { language, .. } => language.as_str(),
+        Command::Chars | Command::Derive { .. } if opts.reject_random_words => "en",
+        command => &command.dict_args()?.language,
+    };
+    let source = opts.dict_source.unwrap_or(if opts.dict_file.is_some() {
+        DictSource::File
+    } else {
+        DictSource::Aspell
+    });
+    Some(match source {
+        DictSource::Aspell => Box::new(words::Aspell {
+            program: opts
+                .aspell_path
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("aspell")),
+            language: language.to_string(),
+        }),
+        DictSource::Wordnet => Box::new(words::WordNet { dir: None }),
+        DictSource::File => Box::new(words::File {
+            path: opts.dict_file.clone()?,
+            min_frequency: opts.dict_min_frequency,
+        }),
+    })
+}
+
+/// Observed frequency of each character class in `sample` next to the
+/// probability with which `do_gen` picks the class. Characters in no class are
+/// reported as "other", so the observed frequencies sum to 1. In dict mode a
+/// word pick contributes many letters at once, so letters are expected to be
+/// over-represented compared to the pick probability.
+pub fn analyze_bias(
+    opts: &Opts,
+    required: &[Constraint],
+    sample: impl Iterator<Item = Generated>,
+) -> Vec<(String, f64, f64)> {
+    let classes = Constraint::value_variants();
+    let mut counts = vec![0usize; classes.len() + 1];
+    for generated in sample {
+        for grapheme in graphemes::graphemes(&generated.password) {
+            let idx = Constraint::classify(opts, grapheme)
+                .and_then(|class| classes.iter().position(|c| *c == class))
+                .unwrap_or(classes.len());
+            counts[idx] += 1;
+        }
+    }
+    let total = counts.iter().sum::<usize>().max(1) as f64;
+    let weights = class_weights(opts, required);
+    let total_weight = weights.iter().sum::<f64>();
+    classes
+        .iter()
+        .map(|class| {
+            let expected = required
+                .iter()
+                .position(|c| c == class)
+                .map_or(0.0, |i| weights[i] / total_weight);
+            let name = class.to_possible_value().unwrap().get_name().to_string();
+            (name, expected)
+        })
+        .chain(std::iter::once(("other".to_string(), 0.0)))
+        .zip(counts)
+        .map(|((name, expected), count)| (name, count as f64 / total, expected))
+        .collect()
+}
+
+/// Quote `s` as a single word for a POSIX shell. Within single quotes every
+/// character is literal except `'` itself, which has to be spliced in as
+/// `'\''`.
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Fill the placeholders of an `--output-template` with the details of
+/// `generated`.
+pub fn render_template(template: &str, generated: &Generated) -> Result<String, String> {
+    let mut out = String::with_capacity(template.len() + generated.password.len());
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        out.push_str(&rest[..i]);
+        let tail = &rest[i..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+        if tail.starts_with('}') {
+            return Err(format!("unmatched '}}' in template '{template}'"));
+        }
+        let end = tail
+            .find('}')
+            .ok_or_else(|| format!("unclosed '{{' in template '{template}'"))?;
+        match &tail[1..end] {
+            "password" => out.push_str(&generated.password),
+            "entropy" => out.push_str(&format!("{:.1}", generated.entropy)),
+            "tries" => out.push_str(&generated.tries.to_string()),
+            "length" => out.push_str(&grapheme_len(&generated.password).to_string()),
+            name => {
+                return Err(format!(
+                    "unknown placeholder {{{name}}} in template '{template}', expected one of \
+                     {{password}}, {{entropy}}, {{tries}} and {{length}}"
+                ))
+            }
+        }
+        rest = &tail[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// The `--audit-log` line for `generated`, made at `time`. It deliberately
+/// leaves out the password.
+pub fn audit_line(opts: &Config, generated: &Generated, time: std::time::SystemTime) -> String {
+    let time = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    format!(
+        "{{\"time\":{time},\"command\":{},\"length\":{},\"tries\":{},\"entropy\":{:.1}}}",
+        json_string(opts.command.name()),
+        grapheme_len(&generated.password),
+        generated.tries,
+        generated.entropy
+    )
+}
+
+/// How `password` is shown in the plain output: the digits of `code` in
+/// groups, `None` for the other commands.
+pub fn display_groups(opts: &Config, password: &str) -> Option<String> {
+    let Command::Code { group_size, .. } = opts.command else {
+        return None;
+    };
+    Some(
+        graphemes::graphemes(password)
+            .collect::<Vec<_>>()
+            .chunks(group_size)
+            .map(|group| group.concat())
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Quote `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `generated` as a JSON object for `--format json`. The entropy is broken
+/// down into the dictionary words and the random letters, digits and
+/// symbols, which add up to the total. For `code` the grouped digits are
+/// included as `display`.
+pub fn to_json(opts: &Config, generated: &Generated) -> String {
+    let mut breakdown = [
+        ("words", 0.0),
+        ("letters", 0.0),
+        ("digits", 0.0),
+        ("symbols", 0.0),
+    ];
+    for token in &generated.tokens {
+        let part = match token.class {
+            _ if token.word => 0,
+            Constraint::LowerCaseLetter | Constraint::UpperCaseLetter => 1,
+            Constraint::Number => 2,
+            Constraint::Symbol => 3,
+        };
+        breakdown[part].1 += token.bits;
+    }
+    let breakdown = breakdown
+        .iter()
+        .map(|(name, bits)| format!("\"{name}\":{bits}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    let display = display_groups(opts, &generated.password)
+        .map(|display| format!("\"display\":{},", json_string(&display)))
+        .unwrap_or_default();
+    format!(
+        "{{\"password\":{},{display}\"length\":{},\"tries\":{},\"entropy\":{},\"entropy_breakdown\":{{{breakdown},\"total\":{}}}}}",
+        json_string(&generated.password),
+        grapheme_len(&generated.password),
+        generated.tries,
+        generated.entropy,
+        generated.entropy
+    )
+}
+
+/// The names of the values of `T` as a JSON array.
+fn json_values<T: ValueEnum>() -> String {
+    let names = T::value_variants()
+        .iter()
+        .filter_map(|v| v.to_possible_value())
+        .map(|v| json_string(v.get_name()))
+        .collect::<Vec<_>>();
+    format!("[{}]", names.join(","))
+}
+
+/// The JSON document of the `capabilities` command: the subcommands of
+/// `cmd` with their options, the constraint classes, output formats, word
+/// sources and random number generators, and the global options.
+pub fn capabilities(mut cmd: clap::Command) -> String {
+    cmd.build();
+    let options = |cmd: &clap::Command| {
+        let names = cmd
+            .get_arguments()
+            .filter(|arg| !arg.is_hide_set())
+            .filter_map(|arg| arg.get_long())
+            .map(|long| json_string(&format!("--{long}")))
+            .collect::<Vec<_>>();
+        format!("[{}]", names.join(","))
+    };
+    let commands = cmd
+        .get_subcommands()
+        .filter(|sub| sub.get_name() != "help")
+        .map(|sub| {
+            format!(
+                "{{\"name\":{},\"about\":{},\"options\":{}}}",
+                json_string(sub.get_name()),
+                json_string(sub.get_about().unwrap_or("")),
+                options(sub)
+            )
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "{{\"version\":{},\"commands\":[{}],\"constraints\":{},\"output_formats\":{},\"dict_sources\":{},\"rngs\":{},\"options\":{}}}",
+        json_string(env!("CARGO_PKG_VERSION")),
+        commands.join(","),
+        json_values::<Constraint>(),
+        json_values::<OutputFormat>(),
+        json_values::<DictSource>(),
+        json_values::<RngKind>(),
+        options(&cmd)
+    )
+}
+
+/// Statistics over a sample of passwords, for comparing configurations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub mean_entropy: f64,
+    pub min_entropy: f64,
+    pub mean_tries: f64,
+    pub min_len: usize,
+    pub max_len: usize,
+}
+
+/// Generate `samples` passwords with `opts` and summarize their strength and
+/// the effort it took to find them.
+pub fn summarize(opts: &Config, samples: usize) -> Result<Summary, GenError> {
+    validate(opts)?;
+    let required = required_classes(opts);
+    let (mut rng, _) = make_rng(opts);
+    let mut summary = Summary {
+        mean_entropy: 0.0,
+        min_entropy: f64::INFINITY,
+        mean_tries: 0.0,
+        min_len: usize::MAX,
+        max_len: 0,
+    };
+    for generated in passwords(opts, &required, &mut *rng)?.take(samples) {
+        let generated = generated?;
+        let len = grapheme_len(&generated.password);
+        summary.mean_entropy += generated.entropy / samples as f64;
+        summary.min_entropy = summary.min_entropy.min(generated.entropy);
+        summary.mean_tries += generated.tries as f64 / samples as f64;
+        summary.min_len = summary.min_len.min(len);
+        summary.max_len = summary.max_len.max(len);
+    }
+    Ok(summary)
+}
+
+/// The throughput measured by the `benchmark` command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Throughput {
+    pub passwords: usize,
+    pub elapsed: Duration,
+    pub mean_tries: f64,
+}
+
+impl Throughput {
+    pub fn per_second(&self) -> f64 {
+        self.passwords as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Generate passwords with `opts` for `duration` and measure how many were
+/// found. Loading the dictionary is not part of the measurement.
+pub fn benchmark(opts: &Config, duration: Duration) -> Result<Throughput, GenError> {
+    validate(opts)?;
+    let required = required_classes(opts);
+    let (mut rng, _) = make_rng(opts);
+    let mut passwords = passwords(opts, &required, &mut *rng)?;
+    let started = std::time::Instant::now();
+    let mut count = 0;
+    let mut tries = 0;
+    // Always measure at least one password
+    while count == 0 || started.elapsed() < duration {
+        tries += passwords.next().unwrap()?.tries as u64;
+        count += 1;
+    }
+    Ok(Throughput {
+        passwords: count,
+        elapsed: started.elapsed(),
+        mean_tries: tries as f64 / count as f64,
+    })
+}
+
+/// How often each reason occurs among the rejections of `sample`, most
+/// common first.
+pub fn tally_rejections(sample: impl Iterator<Item = Generated>) -> Vec<(Rejection, usize)> {
+    count_rejections(sample.flat_map(|generated| generated.rejected))
+}
+
+/// How often each of `rejections` occurs, most common first.
+fn count_rejections(rejections: impl Iterator<Item = Rejection>) -> Vec<(Rejection, usize)> {
+    let mut tally: Vec<(Rejection, usize)> = vec![];
+    for rejection in rejections {
+        match tally.iter_mut().find(|(r, _)| *r == rejection) {
+            Some((_, count)) => *count += 1,
+            None => tally.push((rejection, 1)),
+        }
+    }
+    tally.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    tally
+}
+
+/// Why a configuration cannot be used to generate passwords.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GenError {
+    /// All character classes were excluded
+    NoClasses,
+    /// `--min` is larger than `--max`
+    MinAboveMax { min: usize, max: usize },
+    /// `--min` does not exceed the number of required classes
+    MinTooShort { min: usize, classes: usize },
+    /// The characters for a required class are empty
+    EmptyPool(Constraint),
+    /// Two options contradict each other
+    Conflict(String),
+    /// An option has a value that can never be satisfied
+    Unsatisfiable(String),
+    /// An input could not be read
+    Io(String),
+    /// No candidate passed within `--tries`, `reason` is the most common
+    /// rejection
+    Exhausted {
+        tries: usize,
+        reason: Option<Rejection>,
+    },
+}
+
+impl fmt::Display for GenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenError::NoClasses => write!(f, "All character classes are excluded"),
+            GenError::MinAboveMax { min, max } => {
+                write!(f, "--min {min} is larger than --max {max}")
+            }
+            GenError::MinTooShort { min, classes } => write!(
+                f,
+                "--min {min} must be larger than the number of required classes ({classes})"
+            ),
+            GenError::EmptyPool(class) => write!(
+                f,
+                "{} is required but there are no characters for it",
+                class.to_possible_value().unwrap().get_name()
+            ),
+            GenError::Conflict(msg) | GenError::Unsatisfiable(msg) | GenError::Io(msg) => {
+                f.write_str(msg)
+            }
+            GenError::Exhausted { tries, reason } => {
+                write!(f, "Could not find a satisfactory string in {tries} tries")?;
+                match reason {
+                    Some(reason) => write!(f, " (most common rejection: {reason})"),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for GenError {}
+
+/// The classes a password must contain, after applying `--require`,
+/// `--exclude`, `--letters-only` and `--no-letters`.
+pub fn required_classes(opts: &Config) -> Vec<Constraint> {
+    if opts.require.is_empty() {
+        Either::Left(Constraint::value_variants().iter())
+    } else {
+        Either::Right(opts.require.iter())
+    }
+    .cloned()
+    .filter(|i| !opts.exclude.contains(i) && !opts.shape_excluded().contains(i))
+    .collect()
+}
+
+/// The characters the random picks of each class are drawn from.
+pub struct Pools {
+    pub lower: String,
+    pub upper: String,
+    pub number: String,
+    /// The `--symbols` followed by the `--emoji`
+    pub symbols: Vec<String>,
+}
+
+impl Pools {
+    /// The pool of `class`, one entry per character or emoji.
+    pub fn of(&self, class: Constraint) -> Vec<&str> {
+        let pool = match class {
+            Constraint::LowerCaseLetter => &self.lower,
+            Constraint::UpperCaseLetter => &self.upper,
+            Constraint::Number => &self.number,
+            Constraint::Symbol => return self.symbols.iter().map(String::as_str).collect(),
+        };
+        graphemes::graphemes(pool).collect()
+    }
+}
+
+/// The pools after applying the defaults, custom pools, policies and
+/// exclusions. The pool of a class that is not picked is empty.
+pub fn resolve_pools(opts: &Config) -> Pools {
+    let required = required_classes(opts);
+    let pool = |class, chars: &String| {
+        if required.contains(&class) {
+            chars.clone()
+        } else {
+            String::new()
+        }
+    };
+    Pools {
+        lower: pool(Constraint::LowerCaseLetter, &opts.lower_chars),
+        upper: pool(Constraint::UpperCaseLetter, &opts.upper_chars),
+        number: pool(Constraint::Number, &opts.number_chars),
+        symbols: if required.contains(&Constraint::Symbol) {
+            opts.symbols
+                .chars()
+                .map(String::from)
+                .chain(opts.emoji.iter().cloned())
+                .collect()
+        } else {
+            vec![]
+        },
+    }
+}
+
+/// Check that passwords can be generated with `opts`, without generating
+/// any. Options that are merely likely to cause trouble are reported as
+/// warnings via `log`.
+pub fn validate(opts: &Config) -> Result<(), GenError> {
+    let required = &required_classes(opts);
+    if opts.require_mixed_case {
+        if let Some(c) = opts
+            .exclude
+            .iter()
+            .chain(opts.shape_excluded())
+            .find(|c| matches!(c, Constraint::LowerCaseLetter | Constraint::UpperCaseLetter))
+        {
+            return Err(GenError::Conflict(format!(
+                "--require-mixed-case contradicts excluding {}",
+                c.to_possible_value().unwrap().get_name()
+            )));
+        }
+    }
+    if let Some(c) = opts
+        .require
+        .iter()
+        .find(|c| opts.shape_excluded().contains(c))
+    {
+        return Err(GenError::Conflict(format!(
+            "--require {} contradicts --{}",
+            c.to_possible_value().unwrap().get_name(),
+            if opts.letters_only {
+                "letters-only"
+            } else {
+                "no-letters"
+            }
+        )));
+    }
+    if required.is_empty() {
+        return Err(GenError::NoClasses);
+    }
+    if let Some(template) = &opts.output_template {
+        let example = Generated {
+            password: String::new(),
+            tries: 0,
+            entropy: 0.0,
+            tokens: vec![],
+            rejected: vec![],
+        };
+        render_template(template, &example).map_err(GenError::Unsatisfiable)?;
+    }
+    if let Some(var) = &opts.var {
+        let mut chars = var.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(GenError::Unsatisfiable(format!(
+                "--var {var} is not a valid shell variable name"
+            )));
+        }
+    }
+    let longest = match opts.command {
+        Command::Compact { length, .. } => length,
+        Command::Code { group_size, groups } => group_size.saturating_mul(groups),
+        _ => opts.max,
+    };
+    if longest > opts.max_length_hard_cap {
+        return Err(GenError::Unsatisfiable(format!(
+            "A length of {longest} exceeds the safety limit of {} characters, raise \
+             --max-length-hard-cap if this is intended",
+            opts.max_length_hard_cap
+        )));
+    }
+    if let Command::Code { group_size, groups } = opts.command {
+        if group_size == 0 || groups == 0 {
+            return Err(GenError::Unsatisfiable(
+                "code needs at least one group of at least one digit".to_string(),
+            ));
+        }
+        if opts.number_chars.is_empty() {
+            return Err(GenError::EmptyPool(Constraint::Number));
+        }
+        if let Some(n) = opts.max_class_run.filter(|n| *n < group_size * groups) {
+            return Err(GenError::Unsatisfiable(format!(
+                "--max-class-run {n} cannot be satisfied by a code of {} digits",
+                group_size * groups
+            )));
+        }
+    }
+    // Report the bounds `--target-length` computed in its own terms
+    if let Some(n) = opts.target_length.filter(|_| opts.min <= required.len()) {
+        let band = match opts.jitter {
+            Some(p) => format!("--target-length {n} --jitter {p}"),
+            None => format!("--target-length {n}"),
+        };
+        return Err(GenError::Unsatisfiable(format!(
+            "{band} allows passwords of {} characters, they must be longer than the number of \
+             required classes ({})",
+            opts.min,
+            required.len()
+        )));
+    }
+    if opts.min > opts.max {
+        return Err(GenError::MinAboveMax {
+            min: opts.min,
+            max: opts.max,
+        });
+    }
+    if opts.min <= required.len() {
+        return Err(GenError::MinTooShort {
+            min: opts.min,
+            classes: required.len(),
+        });
+    }
+    if opts.prefer_strong == Some(0) {
+        return Err(GenError::Unsatisfiable(
+            "--prefer-strong must be at least 1".to_string(),
+        ));
+    }
+    if opts.tries == 0 {
+        return Err(GenError::Unsatisfiable(
+            "--tries must be at least 1".to_string(),
+        ));
+    }
+    if let Some(class) = required.iter().copied().find(|class| match class {
+        Constraint::LowerCaseLetter => opts.lower_chars.is_empty(),
+        Constraint::UpperCaseLetter => opts.upper_chars.is_empty(),
+        Constraint::Number => opts.number_chars.is_empty(),
+        Constraint::Symbol => opts.symbols.is_empty() && opts.emoji.is_empty(),
+    }) {
+        return Err(GenError::EmptyPool(class));
+    }
+    for constraint in &opts.position_constraint {
+        let name = constraint.class.to_possible_value().unwrap().get_name();
+        if constraint.index.unsigned_abs() > opts.min {
+            return Err(GenError::Unsatisfiable(format!(
+                "--position-constraint {}:{name} lies beyond --min {}",
+                constraint.index, opts.min
+            )));
+        }
+        if !required.contains(&constraint.class) {
+            return Err(GenError::Conflict(format!(
+                "--position-constraint {}:{name} needs {name}, which is excluded",
+                constraint.index
+            )));
+        }
+    }
+    if opts.username.as_deref() == Some("") {
+        return Err(GenError::Conflict(
+            "--username is empty, every password would contain it".to_string(),
+        ));
+    }
+    if opts.require_non_ambiguous_start {
+        let pools = resolve_pools(opts);
+        let unambiguous = required
+            .iter()
+            .filter(|class| **class != Constraint::Symbol)
+            .flat_map(|class| pools.of(*class))
+            .any(|c| c.chars().count() == 1 && !c.chars().any(is_lookalike));
+        if !unambiguous {
+            return Err(GenError::Unsatisfiable(
+                "--require-non-ambiguous-start needs a letter or number without a lookalike, \
+                 but the required classes have none"
+                    .to_string(),
+            ));
+        }
+    }
+    if let Some(n) = opts.max_class_run {
+        if n == 0 || (required.len() == 1 && n < opts.min) {
+            return Err(GenError::Unsatisfiable(format!(
+                "--max-class-run {n} cannot be satisfied by a password of at least {} \
+                 characters from {} class(es)",
+                opts.min,
+                required.len()
+            )));
+        }
+        if matches!(opts.command, Command::Dict { .. }) {
+            warn!("Dictionary words form long runs of letters, --max-class-run {n} may reject most candidates");
+        }
+    }
+    if let Command::Dict {
+        pad_with_chars: false,
+        ..
+    } = opts.command
+    {
+        if opts.min == opts.max {
+            warn!(
+                "Whole words rarely add up to exactly {} characters, consider --pad-with-chars",
+                opts.min
+            );
+        }
+    }
+    if opts.max_consecutive_vowels.is_some() || opts.max_consecutive_consonants.is_some() {
+        if !matches!(opts.command, Command::Chars | Command::Derive { .. }) {
+            return Err(GenError::Conflict(
+                "--max-consecutive-vowels and --max-consecutive-consonants only apply to chars"
+                    .to_string(),
+            ));
+        }
+        let letters = required
+            .iter()
+            .any(|c| matches!(c, Constraint::LowerCaseLetter | Constraint::UpperCaseLetter));
+        if letters
+            && opts.max_consecutive_vowels == Some(0)
+            && opts.max_consecutive_consonants == Some(0)
+        {
+            return Err(GenError::Unsatisfiable(
+                "--max-consecutive-vowels 0 and --max-consecutive-consonants 0 leave no letters"
+                    .to_string(),
+            ));
+        }
+    }
+    let (min_words, max_words) = opts.command.word_band();
+    let letter_classes = required
+        .iter()
+        .filter(|c| matches!(c, Constraint::LowerCaseLetter | Constraint::UpperCaseLetter))
+        .count();
+    let fewest_words = if opts.legacy_gen {
+        min_words
+    } else {
+        min_words.max(letter_classes)
+    };
+    if min_words > 0 && letter_classes == 0 {
+        return Err(GenError::Conflict(
+            "--min-words requires letters, which are excluded".to_string(),
+        ));
+    }
+    if let Some(n) = max_words.filter(|n| *n < fewest_words) {
+        return Err(GenError::Unsatisfiable(format!(
+            "--max-words {n} is less than the {fewest_words} words every password needs"
+        )));
+    }
+    if opts.command.repeat_window() == Some(0) {
+        return Err(GenError::Unsatisfiable(
+            "--no-repeat-within must be at least 1".to_string(),
+        ));
+    }
+    if let Some((n, threshold)) = opts.command.long_words() {
+        if threshold == 0 {
+            return Err(GenError::Unsatisfiable(
+                "--long-word-threshold must be at least 1".to_string(),
+            ));
+        }
+        if n * threshold > opts.max {
+            return Err(GenError::Unsatisfiable(format!(
+                "--min-long-words {n} needs at least {} letters, more than --max {}",
+                n * threshold,
+                opts.max
+            )));
+        }
+    }
+    if let Some(n) = opts.min_random_digits.filter(|n| *n > 0) {
+        if let Command::Compact { .. } = opts.command {
+            return Err(GenError::Conflict(
+                "--min-random-digits does not apply to compact".to_string(),
+            ));
+        }
+        if !required.contains(&Constraint::Number) {
+            return Err(GenError::Conflict(format!(
+                "--min-random-digits {n} needs numbers, which are excluded"
+            )));
+        }
+        let room = match opts.command {
+            Command::Mixed { random_chars, .. } => random_chars,
+            _ => opts.max,
+        };
+        // Every other required class takes a position as well
+        let others = required.len() - 1;
+        if !opts.legacy_gen && n + others > room {
+            return Err(GenError::Unsatisfiable(format!(
+                "--min-random-digits {n} and one pick of the other {others} classes do not fit \
+                 into {room} characters"
+            )));
+        }
+    }
+    if let Some(n) = opts.min_distinct_symbols.filter(|n| *n > 0) {
+        if let Command::Compact { .. } = opts.command {
+            return Err(GenError::Conflict(
+                "--min-distinct-symbols does not apply to compact".to_string(),
+            ));
+        }
+        if !required.contains(&Constraint::Symbol) {
+            return Err(GenError::Conflict(format!(
+                "--min-distinct-symbols {n} needs symbols, which are excluded"
+            )));
+        }
+        let pool = opts.symbols.chars().count() + opts.emoji.len();
+        if n > pool {
+            return Err(GenError::Unsatisfiable(format!(
+                "--min-distinct-symbols {n} is more than the {pool} symbols to pick from"
+            )));
+        }
+        let room = match opts.command {
+            Command::Mixed { random_chars, .. } => random_chars,
+            _ => opts.max,
+        };
+        // Every other required class takes a position, numbers maybe more
+        let others = required.len() - 1 + opts.min_random_digits.map_or(0, |d| d.saturating_sub(1));
+        if !opts.legacy_gen && n + others > room {
+            return Err(GenError::Unsatisfiable(format!(
+                "--min-distinct-symbols {n} and the picks of the other classes do not fit \
+                 into {room} characters"
+            )));
+        }
+    }
+    for (i, tier) in opts.symbol_tier.iter().enumerate() {
+        if opts.symbol_tier[..i].iter().any(|t| t.name == tier.name) {
+            return Err(GenError::Conflict(format!(
+                "--symbol-tier {} is defined twice",
+                tier.name
+            )));
+        }
+        if let Some(g) = graphemes::graphemes(&tier.symbols).find(|g| {
+            let is_symbol = g.chars().count() == 1 && opts.symbols.contains(*g);
+            !is_symbol && !opts.emoji.iter().any(|e| e == g)
+        }) {
+            return Err(GenError::Conflict(format!(
+                "--symbol-tier {} has {g}, which is not one of the --symbols",
+                tier.name
+            )));
+        }
+    }
+    let bounds = opts
+        .symbol_tier_min
+        .iter()
+        .map(|bound| ("--symbol-tier-min", bound))
+        .chain(
+            opts.symbol_tier_max
+                .iter()
+                .map(|bound| ("--symbol-tier-max", bound)),
+        );
+    for (option, bound) in bounds {
+        if !opts.symbol_tier.iter().any(|tier| tier.name == bound.name) {
+            return Err(GenError::Conflict(format!(
+                "{option} {}={} names no --symbol-tier",
+                bound.name, bound.count
+            )));
+        }
+    }
+    for bound in opts.symbol_tier_min.iter().filter(|bound| bound.count > 0) {
+        if let Command::Compact { .. } | Command::Code { .. } = opts.command {
+            return Err(GenError::Conflict(format!(
+                "--symbol-tier-min does not apply to {}",
+                opts.command.name()
+            )));
+        }
+        if !required.contains(&Constraint::Symbol) {
+            return Err(GenError::Conflict(format!(
+                "--symbol-tier-min {}={} needs symbols, which are excluded",
+                bound.name, bound.count
+            )));
+        }
+        if let Some(max) = opts
+            .symbol_tier_max
+            .iter()
+            .find(|max| max.name == bound.name && max.count < bound.count)
+        {
+            return Err(GenError::Unsatisfiable(format!(
+                "--symbol-tier-min {}={} is more than --symbol-tier-max {}={}",
+                bound.name, bound.count, max.name, max.count
+            )));
+        }
+    }
+    let tier_minimum = opts
+        .symbol_tier_min
+        .iter()
+        .map(|bound| bound.count)
+        .sum::<usize>();
+    if tier_minimum > opts.max {
+        return Err(GenError::Unsatisfiable(format!(
+            "--symbol-tier-min needs {tier_minimum} symbols, more than --max {}",
+            opts.max
+        )));
+    }
+    if let Command::Mixed {
+        word_count,
+        random_chars,
+        ..
+    } = opts.command
+    {
+        if word_count == 0 {
+            return Err(GenError::Unsatisfiable(
+                "mixed needs --word-count of at least 1, use chars for random characters only"
+                    .to_string(),
+            ));
+        }
+        if letter_classes == 0 {
+            return Err(GenError::Conflict(
+                "mixed needs letters for its words, which are excluded".to_string(),
+            ));
+        }
+        let remaining = required.len() - word_count.min(letter_classes);
+        if !opts.legacy_gen && random_chars < remaining {
+            return Err(GenError::Unsatisfiable(format!(
+                "--random-chars {random_chars} is less than the {remaining} character classes \
+                 the words do not cover"
+            )));
+        }
+    }
+    if opts.dict_min_frequency.is_some()
+        && opts
+            .dict_source
+            .is_some_and(|source| source != DictSource::File)
+    {
+        return Err(GenError::Conflict(
+            "--dict-min-frequency only applies to --dict-source file".to_string(),
+        ));
+    }
+    if let Some(program) = &opts.post_filter {
+        if !filter::exists(program) {
+            return Err(GenError::Io(format!(
+                "The post filter {} does not exist",
+                program.display()
+            )));
+        }
+        if opts.count >= POST_FILTER_SLOW_COUNT {
+            warn!(
+                "--post-filter starts {} for every candidate, {} passwords may take a while",
+                program.display(),
+                opts.count
+            );
+        }
+    }
+    if opts.reject_random_words
+        && matches!(opts.command, Command::Dict { .. } | Command::Compact { .. })
+    {
+        return Err(GenError::Conflict(
+            "--reject-random-words only applies to chars and mixed".to_string(),
+        ));
+    }
+    if let Some(dict) = opts.command.dict_args() {
+        if dict.title_case && dict.word_transform == WordTransform::RandomCase {
+            return Err(GenError::Conflict(
+                "--title-case would undo --word-transform random-case".to_string(),
+            ));
+        }
+        if dict.preserve_case && dict.word_transform == WordTransform::RandomCase {
+            return Err(GenError::Conflict(
+                "--preserve-case would be undone by --word-transform random-case".to_string(),
+            ));
+        }
+        if dict.preserve_case && dict.dict_preprocess.contains(&DictPreprocess::Lowercase) {
+            return Err(GenError::Conflict(
+                "--dict-preprocess lowercase removes the case --preserve-case keeps".to_string(),
+            ));
+        }
+    }
+    let fixed_probabilities = [
+        ("symbol", Constraint::Symbol, opts.symbol_probability),
+        ("number", Constraint::Number, opts.number_probability),
+    ];
+    for (name, class, p) in fixed_probabilities {
+        if p.is_none() {
+            continue;
+        }
+        if !matches!(opts.command, Command::Chars) {
+            return Err(GenError::Conflict(format!(
+                "--{name}-probability can only be used with chars"
+            )));
+        }
+        if !required.contains(&class) {
+            return Err(GenError::Conflict(format!(
+                "--{name}-probability is set but {name}s are excluded"
+            )));
+        }
+    }
+    let fixed_total = fixed_probabilities
+        .iter()
+        .filter_map(|(_, class, p)| p.filter(|_| required.contains(class)))
+        .sum::<f64>();
+    let all_fixed = required.iter().all(|c| {
+        fixed_probabilities
+            .iter()
+            .any(|(_, class, p)| class == c && p.is_some())
+    });
+    if fixed_total > 1.0 || (all_fixed && fixed_total == 0.0) {
+        return Err(GenError::Unsatisfiable(format!(
+            "--symbol-probability and --number-probability add up to {fixed_total}, \
+             which leaves no valid distribution over the classes"
+        )));
+    }
+    match (opts.dict_source, &opts.dict_file) {
+        (Some(DictSource::File), None) => {
+            return Err(GenError::Conflict(
+                "--dict-source file requires --dict-file".to_string(),
+            ))
+        }
+        (Some(source @ (DictSource::Aspell | DictSource::Wordnet)), Some(_)) => {
+            return Err(GenError::Conflict(format!(
+                "--dict-file cannot be used with --dict-source {}",
+                source.to_possible_value().unwrap().get_name()
+            )))
+        }
+        _ => (),
+    }
+    let derive = matches!(opts.command, Command::Derive { .. });
+    if derive && !cfg!(feature = "kdf") {
+        return Err(GenError::Conflict(
+            "gen-pw was built without the kdf feature, which derive needs".to_string(),
+        ));
+    }
+    let seeded = opts.seed.is_some() || opts.show_seed || opts.seed_from_file.is_some();
+    let chooses = opts.choose_from.is_some() || opts.choose.is_some();
+    if derive && (seeded || chooses) {
+        return Err(GenError::Conflict(
+            "derive computes its own seed, --seed, --seed-from-file, --show-seed, \
+             --choose-from and --choose cannot be used with it"
+                .to_string(),
+        ));
+    }
+    if opts.choose == Some(0) || opts.choose_from == Some(0) {
+        return Err(GenError::Unsatisfiable(
+            "--choose-from and --choose count the candidates from 1".to_string(),
+        ));
+    }
+    if opts.rng.is_some_and(|kind| kind != RngKind::Chacha) {
+        if derive {
+            return Err(GenError::Conflict(
+                "derive requires --rng chacha".to_string(),
+            ));
+        }
+        if seeded || chooses {
+            return Err(GenError::Conflict(
+                "--seed, --seed-from-file, --show-seed, --choose-from and --choose require \
+                 --rng chacha"
+                    .to_string(),
+            ));
+        }
+    }
+    if opts.classes_order != ClassesOrder::Shuffle {
+        let order = opts.classes_order.to_possible_value().unwrap();
+        if opts.legacy_gen {
+            return Err(GenError::Conflict(format!(
+                "--legacy-gen has its own class order, it cannot be combined with \
+                 --classes-order {}",
+                order.get_name()
+            )));
+        }
+        if opts.classes_order == ClassesOrder::RoundRobin
+            && (opts.symbol_probability.is_some() || opts.number_probability.is_some())
+        {
+            return Err(GenError::Conflict(
+                "--classes-order round-robin ignores --symbol-probability and \
+                 --number-probability, use --classes-order weighted"
+                    .to_string(),
+            ));
+        }
+    }
+    if let Some(warning) = tries_warning(opts, required) {
+        warn!("{warning}");
+    }
+    Ok(())
+}
+
+/// A suggestion to raise `--tries` if the legacy algorithm is unlikely to find
+/// a candidate with all `required` classes within them.
+fn tries_warning(opts: &Opts, required: &[Constraint]) -> Option<String> {
+    if !opts.legacy_gen {
+        return None;
+    }
+    let p = class_coverage_probability(opts, required);
+    let overall = 1.0 - (1.0 - p).powi(opts.tries.min(i32::MAX as usize) as i32);
+    (overall < 0.95).then(|| {
+        let suggested = ((0.01f64).ln() / (1.0 - p).ln()).ceil();
+        format!(
+            "A candidate contains all {} required classes with an estimated probability of \
+             {:.1}%, so --tries {} will likely not suffice. Consider --tries {suggested} or more.",
+            required.len(),
+            p * 100.0,
+            opts.tries
+        )
+    })
+}
+
+/// Counts the calls to the wrapped generator in a counter that can be
+/// shared with the caller, e.g. for `--sample-rng-report`.
+pub struct CountingRng<R: RngCore> {
+    inner: R,
+    draws: Rc<Cell<u64>>,
+}
+
+impl<R: RngCore> CountingRng<R> {
+    pub fn new(inner: R, draws: Rc<Cell<u64>>) -> Self {
+        CountingRng { inner, draws }
+    }
+
+    fn count(&self) {
+        self.draws.set(self.draws.get() + 1);
+    }
+}
+
+impl<R: RngCore> RngCore for CountingRng<R> {
+    fn next_u32(&mut self) -> u32 {
+        self.count();
+        self.inner.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.count();
+        self.inner.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.count();
+        self.inner.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.count();
+        self.inner.try_fill_bytes(dest)
+    }
+}
+
+/// The random number generator selected by `opts` and the seed it was
+/// seeded with, if it is reproducible via `--seed`.
+pub fn make_rng(opts: &Config) -> (Box<dyn RngCore>, Option<u64>) {
+    // The token of `--choose-from` is the seed
+    let seed = opts.seed.or(opts.token).or_else(|| {
+        (opts.show_seed || opts.choose_from.is_some()).then(|| rand::thread_rng().next_u64())
+    });
+    let rng: Box<dyn RngCore> = match (&opts.command, seed, opts.rng) {
+        (
+            Command::Derive {
+                site,
+                master,
+                revision,
+            },
+            _,
+            _,
+        ) => Box::new(rand_chacha::ChaCha20Rng::from_seed(derive_seed(
+            master, site, *revision,
+        ))),
+        (_, Some(seed), _) => Box::new(rand_chacha::ChaCha20Rng::seed_from_u64(seed)),
+        (_, None, _) if opts.file_seed.is_some() => {
+            Box::new(rand_chacha::ChaCha20Rng::from_seed(opts.file_seed.unwrap()))
+        }
+        (_, None, Some(RngKind::Chacha)) => Box::new(rand_chacha::ChaCha20Rng::from_entropy()),
+        (_, None, Some(RngKind::Os)) => Box::new(rand::rngs::OsRng),
+        (_, None, Some(RngKind::Thread) | None) => Box::new(rand::thread_rng()),
+    };
+    (rng, seed)
+}
+
+/// The name of the word source of `opts` and its words, both empty for
+/// commands without a dictionary.
+fn load_dictionary(opts: &Config) -> Result<(String, Vec<String>), GenError> {
+    Ok(match word_source(opts) {
+        Some(source) => (
+            source.name(),
+            source
+                .words()
+                .map_err(|e| GenError::Io(format!("Could not load {}: {e}", source.name())))?,
+        ),
+        None => (String::new(), vec![]),
+    })
+}
+
+/// `count` words sampled uniformly from the filtered dictionary of the
+/// `words` command.
+pub fn sample_words(
+    opts: &Config,
+    count: usize,
+    rng: &mut dyn RngCore,
+) -> Result<Vec<String>, GenError> {
+    let Some(dict) = opts.command.dict_args() else {
+        return Ok(vec![]);
+    };
+    let (source, dictionary) = load_dictionary(opts)?;
+    let words = dict_words(opts, dict, &source, dictionary)?;
+    Ok((0..count)
+        .map(|_| words.choose(rng).unwrap().clone())
+        .collect())
+}
+
+/// An endless stream of passwords for an `opts` that passed [`validate`].
+/// Loads the dictionary for `dict`, `mixed` and `compact` and for
+/// `--reject-random-words`.
+pub fn passwords<'a>(
+    opts: &'a Config,
+    required: &'a [Constraint],
+    rng: &'a mut dyn RngCore,
+) -> Result<Box<dyn Iterator<Item = Result<Generated, GenError>> + 'a>, GenError> {
+    let (source, dictionary) = load_dictionary(opts)?;
+    let avoid = if opts.reject_random_words {
+        dictionary
+            .iter()
+            .filter(|word| grapheme_len(word) >= RANDOM_WORD_MIN_LEN)
+            .map(|word| word.to_lowercase())
+            .collect()
+    } else {
+        HashSet::new()
+    };
+    let passwords: Box<dyn Iterator<Item = Result<Generated, GenError>> + 'a> = match &opts.command
+    {
+        Command::Words { .. } => {
+            return Err(GenError::Conflict(
+                "words prints dictionary words, not passwords".to_string(),
+            ))
+        }
+        Command::Chars
+        | Command::Derive { .. }
+        | Command::SelfTest
+        | Command::Compare { .. }
+        | Command::Benchmark { .. }
+        | Command::Completions { .. }
+        | Command::Capabilities => Box::new(generate_iter(
+            opts,
+            required,
+            chars_picker(opts),
+            None,
+            avoid,
+            rng,
+        )),
+        Command::Dict {
+            dict,
+            pad_with_chars,
+            ..
+        } => {
+            let words = dict_words(opts, dict, &source, dictionary)?;
+            if !opts.legacy_gen {
+                check_word_cases(opts, dict, required, &source, &words)?;
+            }
+            let mut word_lengths = words.iter().map(|w| grapheme_len(w)).collect::<Vec<_>>();
+            word_lengths.sort_unstable();
+            word_lengths.dedup();
+            // Dropping vowels shortens words unpredictably, padding fills
+            // gaps the words leave
+            if dict.word_transform != WordTransform::DropVowels
+                && !pad_with_chars
+                && !dict_length_feasible(opts, required, &word_lengths)
+            {
+                return Err(GenError::Unsatisfiable(if opts.min == opts.max {
+                    format!(
+                        "No combination of words from {source} is exactly {} characters long, \
+                         widen the range with --min and --max or use dict --pad-with-chars",
+                        opts.min
+                    )
+                } else {
+                    format!(
+                        "No combination of words from {source} fits a length between {} and {}, \
+                         widen the range with --min and --max or use chars",
+                        opts.min, opts.max
+                    )
+                }));
+            }
+            let pick_words = dict_picker(opts, dict, words);
+            Box::new(generate_iter(opts, required, pick_words, None, avoid, rng))
+        }
+        Command::Mixed { dict, .. } => {
+            let words = dict_words(opts, dict, &source, dictionary)?;
+            if !opts.legacy_gen {
+                check_word_cases(opts, dict, required, &source, &words)?;
+            }
+            let pick_words = dict_picker(opts, dict, words);
+            Box::new(generate_iter(
+                opts,
+                required,
+                chars_picker(opts),
+                Some(pick_words),
+                avoid,
+                rng,
+            ))
+        }
+        Command::Code { group_size, groups } => {
+            let numbers = opts.number_chars.chars().collect::<Vec<_>>();
+            let bits = bits_of_choice(numbers.len());
+            Box::new(std::iter::repeat_with(move || {
+                // The length options do not apply, but e.g. `--no-leading-zero`
+                // does
+                let mut rejected = vec![];
+                for tries in 1..=opts.tries {
+                    let tokens = (0..group_size * groups)
+                        .map(|_| Token {
+                            text: numbers.choose(rng).unwrap().to_string(),
+                            class: Constraint::Number,
+                            word: false,
+                            bits,
+                        })
+                        .collect::<Vec<_>>();
+                    let password = tokens
+                        .iter()
+                        .map(|token| token.text.as_str())
+                        .collect::<String>();
+                    match secondary_rejection(opts, &password, &[]) {
+                        Some(rejection) => rejected.push(rejection),
+                        None => {
+                            return Ok(Generated {
+                                password,
+                                tries: tries as u32,
+                                entropy: bits * tokens.len() as f64,
+                                tokens,
+                                rejected,
+                            })
+                        }
+                    }
+                }
+                Err(GenError::Exhausted {
+                    tries: opts.tries,
+                    reason: count_rejections(rejected.into_iter())
+                        .first()
+                        .map(|(reason, _)| *reason),
+                })
+            }))
+        }
+        Command::Compact { length, .. } => {
+            let max_word_len = COMPACT_MAX_WORD_LEN.min(length.saturating_sub(1));
+            let words = dictionary
+                .into_iter()
+                .filter(|w| {
+                    !w.is_empty()
+                        && w.chars().count() <= max_word_len
+                        && w.chars().all(char::is_alphabetic)
+                })
+                .collect::<Vec<_>>();
+            if words.is_empty() {
+                return Err(GenError::Unsatisfiable(format!(
+                    "{} has no words of at most {max_word_len} letters",
+                    capitalize(&source)
+                )));
+            }
+            let numbers = opts.number_chars.chars().collect::<Vec<_>>();
+            Box::new(std::iter::repeat_with(move || {
+                let word = words.choose(rng).unwrap();
+                let mut chars = word.chars();
+                let mut password = chars
+                    .next()
+                    .into_iter()
+                    .flat_map(char::to_uppercase)
+                    .chain(chars.flat_map(char::to_lowercase))
+                    .collect::<String>();
+                let digits = length - grapheme_len(&password);
+                let mut tokens = vec![Token {
+                    text: password.clone(),
+                    class: Constraint::UpperCaseLetter,
+                    word: true,
+                    bits: bits_of_choice(words.len()),
+                }];
+                for _ in 0..digits {
+                    let digit = *numbers.choose(rng).unwrap();
+                    password.push(digit);
+                    tokens.push(Token {
+                        text: digit.to_string(),
+                        class: Constraint::Number,
+                        word: false,
+                        bits: bits_of_choice(numbers.len()),
+                    });
+                }
+                Ok(Generated {
+                    password,
+                    tries: 1,
+                    entropy: tokens.iter().map(|token| token.bits).sum(),
+                    tokens,
+                    rejected: vec![],
+                })
+            }))
+        }
+    };
+    Ok(match opts.prefer_strong {
+        Some(k) if k > 1 => Box::new(prefer_strong(passwords, k)),
+        _ => passwords,
+    })
+}
+
+/// The strongest of every `k` passwords from `passwords`. The tries and
+/// rejections of the candidates passed over are added to the chosen one.
+fn prefer_strong<'a>(
+    mut passwords: Box<dyn Iterator<Item = Result<Generated, GenError>> + 'a>,
+    k: usize,
+) -> impl Iterator<Item = Result<Generated, GenError>> + 'a {
+    std::iter::from_fn(move || {
+        let mut tries = 0;
+        let mut rejected = vec![];
+        let candidates = match passwords.by_ref().take(k).collect::<Result<Vec<_>, _>>() {
+            Ok(candidates) => candidates,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut best = candidates
+            .into_iter()
+            .inspect(|generated| {
+                tries += generated.tries;
+                rejected.extend_from_slice(&generated.rejected);
+            })
+            .max_by(|a, b| a.entropy.total_cmp(&b.entropy))?;
+        best.tries = tries;
+        best.rejected = rejected;
+        Some(Ok(best))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn pools_include_their_last_character() {
+        assert!(LOWER_CASE_LETTERS.contains('z'));
+        assert!(UPPER_CASE_LETTERS.contains('Z'));
+        assert!(NUMBERS.contains('9'));
+    }
+
+    #[test]
+    fn humanize_duration_boundaries() {
+        let secs = |n| humanize_duration(Duration::from_secs(n));
+        assert_eq!(
+            humanize_duration(Duration::from_millis(999)),
+            "less than a second"
+        );
+        assert_eq!(secs(1), "1 second");
+        assert_eq!(secs(59), "59 seconds");
+        assert_eq!(secs(60), "1 minute");
+        assert_eq!(secs(3599), "59 minutes");
+        assert_eq!(secs(3600), "1 hour");
+        assert_eq!(secs(365 * 24 * 3600 - 1), "12 months");
+        assert_eq!(secs(365 * 24 * 3600), "1 year");
+        assert_eq!(secs(200 * 365 * 24 * 3600), "2 centuries");
+        assert_eq!(humanize_duration(Duration::MAX), "practically forever");
+    }
+
+    #[test]
+    fn crack_time_searches_half_the_space() {
+        assert_eq!(crack_time(11.0, 1.0), Duration::from_secs(1024));
+        assert_eq!(crack_time(2000.0, 1.0), Duration::MAX);
+    }
+
+    /// The options of the command line `gen-pw args..`.
+    fn parse(args: &[&str]) -> Opts {
+        let mut opts =
+            Opts::try_parse_from(std::iter::once("gen-pw").chain(args.iter().copied())).unwrap();
+        opts.normalize().unwrap();
+        opts
+    }
+
+    /// The first password for `opts`, from a generator seeded with `seed`.
+    fn first(opts: &Opts, seed: u64) -> Result<Generated, GenError> {
+        let required = required_classes(opts);
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(seed);
+        let generated = passwords(opts, &required, &mut rng)?.next().unwrap();
+        generated
+    }
+
+    /// A word list file with `words`, named after the test using it.
+    fn word_list(name: &str, words: &[&str]) -> String {
+        let path = std::env::temp_dir().join(format!("gen-pw-test-{name}.txt"));
+        std::fs::write(&path, words.join("\n")).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    /// The first password for `opts` from the generator the options select.
+    fn password(opts: &Opts) -> String {
+        let required = required_classes(opts);
+        let (mut rng, _) = make_rng(opts);
+        let generated = passwords(opts, &required, &mut *rng).unwrap().next();
+        generated.unwrap().unwrap().password
+    }
+
+    #[cfg(feature = "kdf")]
+    #[test]
+    fn derive_is_deterministic() {
+        let derive = |site, revision| {
+            password(&parse(&[
+                "derive",
+                site,
+                "--master",
+                "correct horse",
+                "--revision",
+                revision,
+            ]))
+        };
+        let first = derive("example.com", "0");
+        assert_eq!(first, derive("example.com", "0"));
+        assert_ne!(first, derive("example.org", "0"));
+        assert_ne!(first, derive("example.com", "1"));
+    }
+
+    #[cfg(feature = "kdf")]
+    #[test]
+    fn seed_file_is_reproducible() {
+        let dir = std::env::temp_dir();
+        let [a, b] = ["a", "b"].map(|name| {
+            let path = dir.join(format!("gen-pw-test-seed-{name}"));
+            std::fs::write(&path, format!("shared secret {name}")).unwrap();
+            path.to_str().unwrap().to_string()
+        });
+        let seeded = |path: &str| password(&parse(&["--seed-from-file", path, "chars"]));
+        assert_eq!(seeded(&a), seeded(&a));
+        assert_ne!(seeded(&a), seeded(&b));
+        let missing = dir.join("gen-pw-test-seed-missing");
+        let mut opts = Opts::try_parse_from([
+            "gen-pw",
+            "--seed-from-file",
+            missing.to_str().unwrap(),
+            "chars",
+        ])
+        .unwrap();
+        assert!(matches!(opts.normalize(), Err(GenError::Io(_))));
+    }
+
+    #[test]
+    fn counts_the_accepted_candidate_as_a_try() {
+        // The constructive algorithm satisfies the classes by construction
+        let opts = parse(&["chars"]);
+        for seed in 0..10 {
+            let generated = first(&opts, seed).unwrap();
+            assert_eq!(generated.tries, 1);
+            assert!(generated.rejected.is_empty());
+        }
+    }
+
+    #[test]
+    fn constructive_candidates_need_no_rejection() {
+        for args in [
+            &["--min", "5", "--max", "5", "chars"][..],
+            &[
+                "--min",
+                "8",
+                "--max",
+                "8",
+                "--min-random-digits",
+                "3",
+                "chars",
+            ],
+            &[
+                "--symbol-probability",
+                "0.01",
+                "--number-probability",
+                "0.01",
+                "chars",
+            ],
+        ] {
+            let opts = parse(args);
+            let required = required_classes(&opts);
+            for seed in 0..50 {
+                let generated = first(&opts, seed).unwrap();
+                let password = &generated.password;
+                assert_eq!(generated.tries, 1, "{password} with {args:?}");
+                assert!(
+                    required.iter().all(|c| c.verify(&opts, password)),
+                    "{password} with {args:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn counts_every_rejected_candidate() {
+        let args = ["--legacy-gen", "--min", "5", "--max", "5"];
+        let opts = parse(&[&args[..], &["chars"]].concat());
+        let (seed, generated) = (0..)
+            .map(|seed| (seed, first(&opts, seed).unwrap()))
+            .find(|(_, generated)| generated.tries >= 3)
+            .unwrap();
+        let k = generated.tries;
+        assert_eq!(generated.rejected.len(), k as usize - 1);
+        // The same seed yields the same candidates, so exactly `k` tries
+        // find the password and one less does not
+        let with_tries = |tries: u32| {
+            let tries = tries.to_string();
+            first(
+                &parse(&[&args[..], &["--tries", &tries, "chars"]].concat()),
+                seed,
+            )
+        };
+        let enough = with_tries(k).unwrap();
+        assert_eq!(enough.password, generated.password);
+        assert_eq!(enough.tries, k);
+        assert!(matches!(
+            with_tries(k - 1),
+            Err(GenError::Exhausted { tries, .. }) if tries == k as usize - 1
+        ));
+    }
+
+    #[test]
+    fn parses_class_specs() {
+        use Constraint::*;
+        let cases: &[(&str, &[Constraint])] = &[
+            ("+lower+upper", &[LowerCaseLetter, UpperCaseLetter]),
+            (
+                "+lower+upper+number-symbol",
+                &[LowerCaseLetter, UpperCaseLetter, Number],
+            ),
+            ("-symbol", &[LowerCaseLetter, UpperCaseLetter, Number]),
+            ("+lower-case-letter+number", &[LowerCaseLetter, Number]),
+            ("-upper-case-letter-number", &[LowerCaseLetter, Symbol]),
+            ("+number+number", &[Number]),
+            ("+symbol-symbol", &[]),
+        ];
+        for (spec, expected) in cases {
+            assert_eq!(parse_classes(spec).as_deref(), Ok(*expected), "{spec}");
+        }
+        for (spec, error) in [
+            ("", "no classes given"),
+            ("lower", "expected + or - before 'lower' in 'lower'"),
+            ("+lower+", "missing class after '+' in '+lower+'"),
+            ("+lower+digits", "unknown class 'digits' in '+lower+digits'"),
+        ] {
+            assert_eq!(parse_classes(spec), Err(error.to_string()), "{spec}");
+        }
+    }
+
+    #[test]
+    fn classes_replace_require_and_exclude() {
+        let opts = parse(&["--classes", "-symbol", "chars"]);
+        assert_eq!(
+            required_classes(&opts),
+            [
+                Constraint::LowerCaseLetter,
+                Constraint::UpperCaseLetter,
+                Constraint::Number
+            ]
+        );
+        assert_eq!(
+            validate(&parse(&["--classes", "+symbol-symbol", "chars"])),
+            Err(GenError::NoClasses)
+        );
+        for other in ["--require", "--exclude"] {
+            let args = ["gen-pw", "--classes", "+lower", other, "number", "chars"];
+            assert!(Opts::try_parse_from(args).is_err(), "{other}");
+        }
+    }
+
+    #[test]
+    fn renders_templates() {
+        let generated = Generated {
+            password: "e\u{301}x4!".to_string(),
+            tries: 3,
+            entropy: 41.26,
+            tokens: vec![],
+            rejected: vec![],
+        };
+        assert_eq!(
+            render_template(
+                "{password} ({entropy} bits, {tries} tries, {length})",
+                &generated
+            )
+            .as_deref(),
+            Ok(if cfg!(feature = "graphemes") {
+                "e\u{301}x4! (41.3 bits, 3 tries, 4)"
+            } else {
+                "e\u{301}x4! (41.3 bits, 3 tries, 5)"
+            })
+        );
+        assert_eq!(
+            render_template("{{password}} {password}}}", &generated).as_deref(),
+            Ok("{password} e\u{301}x4!}")
+        );
+        for (template, error) in [
+            ("{secret}", "unknown placeholder {secret}"),
+            ("{password", "unclosed '{'"),
+            ("password}", "unmatched '}'"),
+        ] {
+            let e = render_template(template, &generated).unwrap_err();
+            assert!(e.starts_with(error), "{template}: {e}");
+        }
+        assert!(matches!(
+            validate(&parse(&["--output-template", "{pw}", "chars"])),
+            Err(GenError::Unsatisfiable(_))
+        ));
+    }
+
+    /// Candidates for the class tracking tests, with characters of every
+    /// class, none and emoji.
+    const CLASS_SAMPLES: &[&str] = &[
+        "",
+        "a",
+        "Z9",
+        "abc",
+        "ABC!",
+        "a1B2c3",
+        "ü€ß",
+        "-_/",
+        "x🔑",
+        "👍🏽",
+        "aB3$🔑Zq",
+        "e\u{301}",
+    ];
+
+    #[test]
+    fn class_set_agrees_with_verify() {
+        for args in [
+            &["chars"][..],
+            &["--emoji", "🔑,👍🏽", "chars"],
+            &["--lower-chars", "abc", "--symbols", "ü", "chars"],
+        ] {
+            let opts = parse(args);
+            for s in CLASS_SAMPLES {
+                let set = ClassSet::of(&opts, s);
+                for class in Constraint::value_variants() {
+                    assert_eq!(
+                        set.contains(*class),
+                        class.verify(&opts, s),
+                        "{class:?} in {s:?} with {args:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn incremental_class_tracking_matches_a_full_scan() {
+        let opts = parse(&["--emoji", "🔑,👍🏽", "chars"]);
+        // Without the feature 👍🏽 splits into two characters that are no emoji
+        for s in CLASS_SAMPLES.iter().filter(|_| cfg!(feature = "graphemes")) {
+            // do_gen adds the classes of every pick as it is pushed
+            let mut present = ClassSet::default();
+            for pick in graphemes::graphemes(s) {
+                present.extend(ClassSet::of(&opts, pick));
+            }
+            assert_eq!(present, ClassSet::of(&opts, s), "{s:?}");
+        }
+        // do_gen checks its tracking against a full scan in debug builds
+        let opts = parse(&[
+            "--max-length-hard-cap",
+            "4096",
+            "--min",
+            "4000",
+            "--max",
+            "4096",
+            "--emoji",
+            "🔑",
+            "chars",
+        ]);
+        let generated = first(&opts, 3).unwrap();
+        assert!(required_classes(&opts)
+            .iter()
+            .all(|class| class.verify(&opts, &generated.password)));
+    }
+
+    #[test]
+    fn abbreviates_long_candidates() {
+        assert_eq!(abbreviate("abc", 3), "abc");
+        assert_eq!(abbreviate("abcd", 3), "abc…");
+        #[cfg(feature = "graphemes")]
+        assert_eq!(abbreviate("ae\u{301}b", 2), "ae\u{301}…");
+    }
+
+    #[test]
+    fn validate_reports_each_error() {
+        let cases: &[(&[&str], GenError)] = &[
+            (
+                &[
+                    "--no-letters",
+                    "--exclude",
+                    "number",
+                    "--exclude",
+                    "symbol",
+                    "chars",
+                ],
+                GenError::NoClasses,
+            ),
+            (
+                &["--min", "20", "--max", "10", "chars"],
+                GenError::MinAboveMax { min: 20, max: 10 },
+            ),
+            (
+                &["--min", "4", "--max", "10", "chars"],
+                GenError::MinTooShort { min: 4, classes: 4 },
+            ),
+            (
+                &["--number-chars", "", "chars"],
+                GenError::EmptyPool(Constraint::Number),
+            ),
+            (
+                &["--tries", "0", "chars"],
+                GenError::Unsatisfiable("--tries must be at least 1".to_string()),
+            ),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(validate(&parse(args)).as_ref(), Err(expected), "{args:?}");
+        }
+        assert_eq!(validate(&parse(&["chars"])), Ok(()));
+    }
+
+    #[test]
+    fn exhaustion_is_an_error() {
+        // One try can not fit four classes into five characters every time
+        let opts = parse(&[
+            "--legacy-gen",
+            "--tries",
+            "1",
+            "--min",
+            "5",
+            "--max",
+            "5",
+            "chars",
+        ]);
+        let errors = (0..20)
+            .filter_map(|seed| first(&opts, seed).err())
+            .collect::<Vec<_>>();
+        assert!(!errors.is_empty());
+        for e in errors {
+            let GenError::Exhausted { tries, reason } = e else {
+                panic!("{e}");
+            };
+            assert_eq!(tries, 1);
+            assert!(matches!(reason, Some(Rejection::Missing(_))), "{reason:?}");
+        }
+    }
+
+    #[test]
+    fn warns_about_few_tries() {
+        let warning = |args: &[&str]| {
+            let opts = parse(args);
+            tries_warning(&opts, &required_classes(&opts))
+        };
+        let few = warning(&["--legacy-gen", "--tries", "1", "chars"]).unwrap();
+        assert!(few.contains("all 4 required classes"), "{few}");
+        assert!(few.contains("--tries 1 will likely not suffice"), "{few}");
+        assert_eq!(warning(&["--legacy-gen", "--tries", "1000", "chars"]), None);
+        // The constructive algorithm does not depend on chance
+        assert_eq!(warning(&["--tries", "1", "chars"]), None);
+    }
+
+    #[test]
+    fn best_effort_when_exhausted() {
+        // One word and four characters never reach 20
+        let list = word_list("best-effort", &["apple", "mountain"]);
+        let args = [
+            "--dict-file",
+            &list,
+            "--min",
+            "20",
+            "--max",
+            "24",
+            "--tries",
+            "5",
+            "mixed",
+            "--word-count",
+            "1",
+            "--random-chars",
+            "4",
+        ];
+        let strict = parse(&args);
+        assert!(matches!(
+            first(&strict, 1),
+            Err(GenError::Exhausted {
+                reason: Some(Rejection::TooShort),
+                ..
+            })
+        ));
+        let relaxed = parse(&[&["--allow-shorter-than-min-if-exhausted"], &args[..]].concat());
+        let required = required_classes(&relaxed);
+        for seed in 0..10 {
+            let generated = first(&relaxed, seed).unwrap();
+            let len = grapheme_len(&generated.password);
+            assert!(len < relaxed.min, "{}", generated.password);
+            assert!(required
+                .iter()
+                .all(|c| c.verify(&relaxed, &generated.password)));
+            assert_eq!(generated.tries, 5);
+        }
+        // Each round of --auto-relax makes another 5 tries
+        let relaxing = parse(
+            &[
+                &[
+                    "--allow-shorter-than-min-if-exhausted",
+                    "--auto-relax",
+                    "--reject-random-words",
+                ],
+                &args[..],
+            ]
+            .concat(),
+        );
+        let generated = first(&relaxing, 1).unwrap();
+        assert_eq!(generated.tries, 10);
+        assert_eq!(generated.rejected.len(), 10);
+    }
+
+    #[test]
+    fn best_effort_passes_the_post_filter() {
+        let list = word_list("post-filter", &["apple", "mountain"]);
+        let opts = parse(&[
+            "--dict-file",
+            &list,
+            "--min",
+            "20",
+            "--max",
+            "24",
+            "--tries",
+            "5",
+            "--allow-shorter-than-min-if-exhausted",
+            "--post-filter",
+            "false",
+            "mixed",
+            "--word-count",
+            "1",
+            "--random-chars",
+            "4",
+        ]);
+        assert!(matches!(
+            first(&opts, 1),
+            Err(GenError::Exhausted { tries: 5, .. })
+        ));
+        // Candidates of full length that only the filter rejected
+        let opts = parse(&[
+            "--tries",
+            "5",
+            "--allow-shorter-than-min-if-exhausted",
+            "--post-filter",
+            "false",
+            "chars",
+        ]);
+        assert!(matches!(
+            first(&opts, 1),
+            Err(GenError::Exhausted {
+                tries: 5,
+                reason: Some(Rejection::PostFilter)
+            })
+        ));
+    }
+
+    #[test]
+    fn missing_post_filter_is_an_error() {
+        let opts = parse(&["--post-filter", "gen-pw-test-no-such-filter", "chars"]);
+        assert!(matches!(validate(&opts), Err(GenError::Io(_))));
+        assert!(matches!(first(&opts, 1), Err(GenError::Io(_))));
+        let opts = parse(&["--post-filter", "true", "chars"]);
+        assert_eq!(validate(&opts), Ok(()));
+        assert!(first(&opts, 1).is_ok());
+    }
+
+    #[test]
+    fn azerty_greater_than_needs_shift() {
+        assert_eq!(
+            keyboard_warnings(KeyboardLayout::Azerty, "a>b"),
+            ["> needs Shift on azerty"]
+        );
+        assert!(keyboard_warnings(KeyboardLayout::Azerty, "abc").is_empty());
+    }
+
+    #[test]
+    fn padding_an_overlong_candidate() {
+        let list = word_list("padding", &["apple", "mountain", "river", "banana"]);
+        let opts = parse(&[
+            "--dict-file",
+            &list,
+            "--min",
+            "10",
+            "--max",
+            "10",
+            "--tries",
+            "50",
+            "dict",
+            "--pad-with-chars",
+            "--min-words",
+            "3",
+        ]);
+        for seed in 0..20 {
+            if let Ok(generated) = first(&opts, seed) {
+                assert_eq!(grapheme_len(&generated.password), 10);
+            }
+        }
+    }
+
+    #[test]
+    fn code_has_no_leading_zero() {
+        let opts = parse(&["--no-leading-zero", "code", "--group-size", "1"]);
+        assert_eq!(validate(&opts), Ok(()));
+        for seed in 0..100 {
+            let generated = first(&opts, seed).unwrap();
+            assert!(
+                !generated.password.starts_with('0'),
+                "{}",
+                generated.password
+            );
+        }
+        // One in ten codes starts with 0 otherwise
+        let opts = parse(&["code", "--group-size", "1"]);
+        assert!((0..100).any(|seed| first(&opts, seed).unwrap().password.starts_with('0')));
+        let opts = parse(&["--max-class-run", "3", "code"]);
+        assert!(matches!(validate(&opts), Err(GenError::Unsatisfiable(_))));
+    }
+
+    #[test]
+    fn codes_are_grouped_digits() {
+        let opts = parse(&["code", "--group-size", "4", "--groups", "3"]);
+        for seed in 0..10 {
+            let generated = first(&opts, seed).unwrap();
+            let code = &generated.password;
+            assert_eq!(code.len(), 12);
+            assert!(code.chars().all(|c| c.is_ascii_digit()), "{code}");
+            let display = display_groups(&opts, code).unwrap();
+            assert_eq!(
+                display.split(' ').map(str::len).collect::<Vec<_>>(),
+                [4, 4, 4]
+            );
+            assert_eq!(display.replace(' ', ""), *code);
+            assert!(to_json(&opts, &generated).contains(&format!("\"display\":\"{display}\"")));
+        }
+        assert_eq!(display_groups(&parse(&["chars"]), "abc"), None);
+    }
+
+    #[test]
+    fn mixed_needs_a_word() {
+        let opts = parse(&["mixed", "--word-count", "0"]);
+        assert!(matches!(validate(&opts), Err(GenError::Unsatisfiable(_))));
+        let opts = parse(&["--no-letters", "mixed"]);
+        assert!(matches!(validate(&opts), Err(GenError::Conflict(_))));
+        assert_eq!(validate(&parse(&["mixed", "--word-count", "2"])), Ok(()));
+    }
+
+    #[test]
+    fn target_length_errors_name_target_length() {
+        let opts = parse(&["--target-length", "4", "--jitter", "0.25", "chars"]);
+        let message = validate(&opts).unwrap_err().to_string();
+        assert!(
+            message.starts_with("--target-length 4 --jitter 0.25 allows passwords of 3 characters"),
+            "{message}"
+        );
+        assert!(!message.contains("--min"), "{message}");
+        let opts = parse(&["--target-length", "16", "--jitter", "0.2", "chars"]);
+        assert_eq!((opts.min, opts.max), (13, 19));
+        assert_eq!(validate(&opts), Ok(()));
+        for seed in 0..20 {
+            let len = grapheme_len(&first(&opts, seed).unwrap().password);
+            assert!((13..=19).contains(&len), "{len}");
+        }
+    }
+
+    #[test]
+    fn word_transforms() {
+        let cases = [
+            (WordTransform::None, "Horse", "Horse"),
+            (WordTransform::Reverse, "Horse", "esroH"),
+            (WordTransform::Reverse, "café", "éfac"),
+            (WordTransform::DropVowels, "Battery", "Bttry"),
+            (WordTransform::DropVowels, "AUDIO", "D"),
+        ];
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(1);
+        for (transform, word, expected) in cases {
+            assert_eq!(transform.apply_fixed(word).as_deref(), Some(expected));
+            assert_eq!(
+                transform.apply(word, None, &mut rng),
+                (expected.to_string(), 0.0)
+            );
+        }
+        assert_eq!(WordTransform::RandomCase.apply_fixed("horse"), None);
+        let (cased, bits) = WordTransform::RandomCase.apply("horse-1", None, &mut rng);
+        assert_eq!(cased.to_lowercase(), "horse-1");
+        // One bit per letter
+        assert_eq!(bits, 5.0);
+        let (cased, _) =
+            WordTransform::RandomCase.apply("iiiiiiii", Some(CasingLocale::Tr), &mut rng);
+        assert!(cased.chars().all(|c| c == 'i' || c == 'İ'), "{cased}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shell_quote_round_trips() {
+        for s in [
+            "",
+            "plain",
+            "two words",
+            "it's",
+            "''",
+            "$HOME `id` \\ \"*\"",
+            "a\nb",
+        ] {
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("printf %s {}", shell_quote(s)))
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), s);
+        }
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn entropy_breakdown_adds_up() {
+        let list = word_list("breakdown", &["apple", "mountain", "river", "stone"]);
+        for args in [
+            &["chars"][..],
+            &[
+                "--dict-file",
+                &list,
+                "--max",
+                "30",
+                "mixed",
+                "--word-count",
+                "2",
+            ],
+            &["--dict-file", &list, "--max", "30", "dict"],
+        ] {
+            let opts = parse(args);
+            let generated = first(&opts, 3).unwrap();
+            let json = to_json(&opts, &generated);
+            let (_, breakdown) = json.split_once("\"entropy_breakdown\":{").unwrap();
+            let parts = breakdown
+                .trim_end_matches('}')
+                .split(',')
+                .map(|part| {
+                    let (name, bits) = part.split_once(':').unwrap();
+                    (name.trim_matches('"'), bits.parse::<f64>().unwrap())
+                })
+                .collect::<Vec<_>>();
+            let names = parts.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            assert_eq!(names, ["words", "letters", "digits", "symbols", "total"]);
+            let sum = parts[..4].iter().map(|(_, bits)| bits).sum::<f64>();
+            assert!((sum - generated.entropy).abs() < 1e-9, "{json}");
+            assert_eq!(parts[4].1, generated.entropy);
+            let words = parts[0].1;
+            assert_eq!(words > 0.0, args[0] == "--dict-file", "{json}");
+        }
+    }
+
+    #[test]
+    fn round_robin_rotates_the_classes() {
+        let opts = parse(&["--classes-order", "round-robin", "--min", "12", "chars"]);
+        let required = required_classes(&opts);
+        for seed in 0..5 {
+            let generated = first(&opts, seed).unwrap();
+            let classes = graphemes::graphemes(&generated.password)
+                .map(|g| Constraint::classify(&opts, g).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(classes.len(), 12);
+            for (i, class) in classes.iter().enumerate() {
+                assert_eq!(
+                    *class,
+                    required[i % required.len()],
+                    "{}",
+                    generated.password
+                );
+            }
+        }
+        // The same seed gives the same password
+        assert_eq!(
+            first(&opts, 9).unwrap().password,
+            first(&opts, 9).unwrap().password
+        );
+    }
+
+    #[cfg(not(feature = "kdf"))]
+    #[test]
+    fn derive_needs_the_kdf_feature() {
+        let opts = parse(&["derive", "example.com", "--master", "secret"]);
+        assert!(matches!(validate(&opts), Err(GenError::Conflict(_))));
+        let mut opts =
+            Opts::try_parse_from(["gen-pw", "--seed-from-file", "seed", "chars"]).unwrap();
+        assert!(matches!(opts.normalize(), Err(GenError::Conflict(_))));
+    }
+
+    #[test]
+    fn custom_pools_are_used_and_verified() {
+        let opts = parse(&[
+            "--lower-chars",
+            "ab",
+            "--upper-chars",
+            "XY",
+            "--number-chars",
+            "234567",
+            "--symbols",
+            "#",
+            "chars",
+        ]);
+        for seed in 0..20 {
+            let password = first(&opts, seed).unwrap().password;
+            assert!(
+                password.chars().all(|c| "abXY234567#".contains(c)),
+                "{password}"
+            );
+            for class in required_classes(&opts) {
+                assert!(class.verify(&opts, &password), "{class:?} in {password}");
+            }
+        }
+        // The default pools no longer count
+        assert!(!Constraint::LowerCaseLetter.verify(&opts, "xyz"));
+        assert!(!Constraint::UpperCaseLetter.verify(&opts, "ABC"));
+        assert!(!Constraint::Number.verify(&opts, "189"));
+        assert!(!Constraint::Symbol.verify(&opts, "!@"));
+        assert!(Constraint::Number.verify(&opts, "x7"));
+    }
+
+    #[test]
+    fn apostrophe_words_only_when_kept() {
+        let list = word_list("apostrophes", &["don't", "l'eau", "cat"]);
+        let sample = |args: &[&str]| {
+            let opts = parse(&[&["--dict-file", &list, "words"], args].concat());
+            let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(5);
+            sample_words(&opts, 50, &mut rng).unwrap()
+        };
+        assert!(sample(&[]).iter().all(|word| word == "cat"));
+        let kept = sample(&["--keep-apostrophes"]);
+        assert!(kept.iter().any(|word| word.contains('\'')), "{kept:?}");
+
+        // The apostrophe counts towards the length, but is no symbol by default
+        let opts = parse(&[
+            "--dict-file",
+            &list,
+            "--min",
+            "5",
+            "--max",
+            "5",
+            "--classes",
+            "+upper",
+            "dict",
+            "--keep-apostrophes",
+        ]);
+        let password = first(&opts, 1).unwrap().password;
+        assert!(password.contains('\''), "{password}");
+        assert!(!Constraint::Symbol.verify(&opts, &password));
+        let opts = parse(&["--symbols", "'", "chars"]);
+        assert!(Constraint::Symbol.verify(&opts, "don't"));
+    }
+
+    #[test]
+    fn letter_toggles_shape_the_output() {
+        let opts = parse(&["--letters-only", "chars"]);
+        for seed in 0..10 {
+            let password = first(&opts, seed).unwrap().password;
+            assert!(password.chars().all(char::is_alphabetic), "{password}");
+        }
+        let opts = parse(&["--no-letters", "chars"]);
+        for seed in 0..10 {
+            let password = first(&opts, seed).unwrap().password;
+            assert!(!password.chars().any(char::is_alphabetic), "{password}");
+        }
+        for args in [
+            &["--letters-only", "--require", "number", "chars"][..],
+            &["--no-letters", "--require", "upper-case-letter", "chars"],
+        ] {
+            let e = validate(&parse(args));
+            assert!(matches!(&e, Err(GenError::Conflict(_))), "{args:?}");
+        }
+        assert!(
+            Opts::try_parse_from(["gen-pw", "--letters-only", "--no-letters", "chars"]).is_err()
+        );
+    }
+
+    #[test]
+    fn bias_frequencies_sum_to_one() {
+        let opts = parse(&["--exclude", "symbol", "chars"]);
+        let required = required_classes(&opts);
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(9);
+        let sample = passwords(&opts, &required, &mut rng)
+            .unwrap()
+            .take(200)
+            .map(Result::unwrap);
+        let report = analyze_bias(&opts, &required, sample);
+        let observed = report.iter().map(|(_, observed, _)| observed).sum::<f64>();
+        let expected = report.iter().map(|(_, _, expected)| expected).sum::<f64>();
+        assert!((observed - 1.0).abs() < 1e-9, "{observed}");
+        assert!((expected - 1.0).abs() < 1e-9, "{expected}");
+        for (name, observed, expected) in &report {
+            match name.as_str() {
+                "symbol" | "other" => assert_eq!((*observed, *expected), (0.0, 0.0)),
+                _ => assert!((observed - expected).abs() < 0.05, "{name}: {observed}"),
+            }
+        }
+    }
+
+    #[test]
+    fn compact_is_a_capitalized_word_and_digits() {
+        let list = word_list("compact", &["horse", "Battery", "ox", "staple"]);
+        let opts = parse(&["--dict-file", &list, "compact"]);
+        for seed in 0..20 {
+            let password = first(&opts, seed).unwrap().password;
+            assert_eq!(password.len(), 8, "{password}");
+            assert!(
+                password.starts_with(|c: char| c.is_ascii_uppercase()),
+                "{password}"
+            );
+            let word = password.trim_end_matches(|c: char| c.is_ascii_digit());
+            assert!(word.len() <= 6 && word.len() < password.len(), "{password}");
+            assert!(["Horse", "Ox", "Staple"].contains(&word), "{password}");
+        }
+    }
+
+    #[test]
+    fn whitespace_is_stripped_from_symbols() {
+        let opts = parse(&["--symbols", " !@#\t", "chars"]);
+        assert_eq!(opts.symbols, "!@#");
+        let opts = parse(&["--symbols", " !@# ", "--allow-space-symbol", "chars"]);
+        assert_eq!(opts.symbols, " !@#");
+        let path = std::env::temp_dir().join("gen-pw-test-symbols-file.txt");
+        std::fs::write(&path, "! @\n#\n").unwrap();
+        let opts = parse(&["--symbols-file", path.to_str().unwrap(), "chars"]);
+        assert_eq!(opts.symbols, "!@#");
+        let opts = parse(&["--symbols", " !", "--classes", "+symbol", "chars"]);
+        assert!(!first(&opts, 2).unwrap().password.contains(' '));
+    }
+
+    #[test]
+    fn class_runs_stay_within_the_limit() {
+        let opts = parse(&["--max-class-run", "2", "chars"]);
+        assert!(validate(&opts).is_ok());
+        for seed in 0..20 {
+            let password = first(&opts, seed).unwrap().password;
+            assert!(longest_class_run(&opts, &password) <= 2, "{password}");
+        }
+        assert_eq!(longest_class_run(&opts, "ab12CD!x"), 2);
+        assert_eq!(longest_class_run(&opts, "abc1"), 3);
+        // A single class cannot fill 10 characters in runs of 2
+        let opts = parse(&["--max-class-run", "2", "--classes", "+number", "chars"]);
+        assert!(matches!(validate(&opts), Err(GenError::Unsatisfiable(_))));
+    }
+
+    #[test]
+    fn ascii_only_rejects_non_ascii_words() {
+        let list = word_list("ascii-only", &["café", "über", "mountain", "river"]);
+        let opts = parse(&["--dict-file", &list, "--ascii-only", "dict"]);
+        let mut rejected = vec![];
+        for seed in 0..20 {
+            let generated = first(&opts, seed).unwrap();
+            assert!(generated.password.is_ascii(), "{}", generated.password);
+            rejected.extend(generated.rejected);
+        }
+        assert!(rejected.contains(&Rejection::NotAscii), "{rejected:?}");
+        let opts = parse(&["--dict-file", &list, "dict"]);
+        assert!((0..20).any(|seed| !first(&opts, seed).unwrap().password.is_ascii()));
+    }
+
+    #[test]
+    fn explanation_lists_the_picks_of_the_password() {
+        let list = word_list("explain", &["apple", "mountain", "river", "stone"]);
+        let opts = parse(&["--dict-file", &list, "dict"]);
+        for seed in 0..10 {
+            let Generated {
+                password, tokens, ..
+            } = first(&opts, seed).unwrap();
+            let joined = tokens.iter().map(|t| t.text.as_str()).collect::<String>();
+            assert_eq!(joined, password);
+            for token in &tokens {
+                if token.word {
+                    assert_eq!(token.source(), "dictionary word");
+                    let word = token.text.to_lowercase();
+                    assert!(["apple", "mountain", "river", "stone"].contains(&word.as_str()));
+                } else {
+                    assert!(token.source().starts_with("random "), "{token:?}");
+                    assert!(token.class.verify(&opts, &token.text), "{token:?}");
+                }
+            }
+            assert!(tokens.iter().any(|t| t.word) && tokens.iter().any(|t| !t.word));
+        }
+    }
+
+    #[test]
+    fn symbol_frequency_tracks_the_probability() {
+        let symbol_share = |p: &str| {
+            let opts = parse(&["--min", "20", "--symbol-probability", p, "chars"]);
+            let required = required_classes(&opts);
+            let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(4);
+            let sample = passwords(&opts, &required, &mut rng)
+                .unwrap()
+                .take(200)
+                .map(|generated| generated.unwrap().password)
+                .collect::<String>();
+            let symbols = sample.chars().filter(|c| opts.symbols.contains(*c)).count();
+            symbols as f64 / sample.chars().count() as f64
+        };
+        for p in [0.1, 0.3, 0.6] {
+            // One position per class is taken by construction, the other 16
+            // are picked with the probability
+            let expected = (1.0 + 16.0 * p) / 20.0;
+            let share = symbol_share(&p.to_string());
+            assert!((share - expected).abs() < 0.03, "{p}: {share}");
+        }
+        assert!(Opts::try_parse_from(["gen-pw", "--symbol-probability", "1.5", "chars"]).is_err());
+    }
+
+    #[test]
+    fn word_count_stays_in_the_band() {
+        let list = word_list("word-band", &["ox", "cat", "apple", "stone", "river"]);
+        let opts = parse(&[
+            "--dict-file",
+            &list,
+            "--min",
+            "8",
+            "--max",
+            "30",
+            "dict",
+            "--min-words",
+            "3",
+            "--max-words",
+            "4",
+        ]);
+        for seed in 0..20 {
+            let generated = first(&opts, seed).unwrap();
+            let words = generated.tokens.iter().filter(|t| t.word).count();
+            assert!((3..=4).contains(&words), "{}", generated.password);
+        }
+    }
+
+    #[test]
+    fn unreachable_dict_lengths_fail_early() {
+        let list = word_list("unreachable", &["river", "stone"]);
+        let unsatisfiable = |min: &str, max: &str| {
+            let opts = parse(&[
+                "--dict-file",
+                &list,
+                "--classes",
+                "+upper",
+                "--min",
+                min,
+                "--max",
+                max,
+                "dict",
+            ]);
+            let required = required_classes(&opts);
+            let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(0);
+            let error = passwords(&opts, &required, &mut rng).err();
+            match error {
+                Some(GenError::Unsatisfiable(e)) => e,
+                _ => panic!("{min}-{max} is satisfiable"),
+            }
+        };
+        // One word is 5 letters, two are 10
+        let e = unsatisfiable("6", "9");
+        assert!(e.contains("fits a length between 6 and 9"), "{e}");
+        let e = unsatisfiable("7", "7");
+        assert!(e.contains("is exactly 7 characters long"), "{e}");
+    }
+
+    #[test]
+    fn rng_backends() {
+        let seeded = parse(&["--rng", "chacha", "--seed", "11", "chars"]);
+        assert_eq!(password(&seeded), password(&seeded));
+        assert_eq!(password(&seeded), first(&seeded, 11).unwrap().password);
+        for kind in ["chacha", "os", "thread"] {
+            let opts = parse(&["--rng", kind, "chars"]);
+            assert!(validate(&opts).is_ok(), "{kind}");
+            let password = password(&opts);
+            assert!((10..=20).contains(&password.len()), "{kind}: {password}");
+            for class in required_classes(&opts) {
+                assert!(class.verify(&opts, &password), "{kind}: {password}");
+            }
+        }
+        for kind in ["os", "thread"] {
+            let opts = parse(&["--rng", kind, "--seed", "11", "chars"]);
+            assert!(
+                matches!(validate(&opts), Err(GenError::Conflict(_))),
+                "{kind}"
+            );
+        }
+    }
+
+    #[test]
+    fn legend_lists_only_present_lookalikes() {
+        assert_eq!(
+            lookalike_legend("xl0q"),
+            [
+                "contains lower case L (l), not one (1) or upper case i (I) or vertical bar (|)",
+                "contains zero (0), not upper case o (O) or lower case o (o)",
+            ]
+        );
+        assert_eq!(
+            lookalike_legend("S5"),
+            [
+                "contains upper case s (S), not five (5)",
+                "contains five (5), not upper case s (S)",
+            ]
+        );
+        assert!(lookalike_legend("acdefg-_").is_empty());
+    }
+
+    #[test]
+    fn diversity_avoids_repeats() {
+        let distinct = |s: &str| s.chars().collect::<HashSet<_>>().len();
+        // 20 characters from a pool of 22
+        let opts = parse(&[
+            "--maximize-diversity",
+            "--min",
+            "20",
+            "--lower-chars",
+            "abcdefgh",
+            "--upper-chars",
+            "ABCDEFGH",
+            "--number-chars",
+            "123",
+            "--symbols",
+            "!@#",
+            "chars",
+        ]);
+        for seed in 0..20 {
+            let password = first(&opts, seed).unwrap().password;
+            assert_eq!(distinct(&password), 20, "{password}");
+        }
+        // Falls back to repeats once the pool is used up
+        let opts = parse(&["--maximize-diversity", "--classes", "+number", "chars"]);
+        let password = first(&opts, 0).unwrap().password;
+        assert_eq!(distinct(&password), 10, "{password}");
+    }
+
+    #[test]
+    fn tiny_dictionaries_are_clean_errors() {
+        let error = |name, words: &[&str]| {
+            let list = word_list(name, words);
+            let opts = parse(&["--dict-file", &list, "--max", "12", "dict"]);
+            let required = required_classes(&opts);
+            let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(0);
+            let error = passwords(&opts, &required, &mut rng).err();
+            match error {
+                Some(GenError::Unsatisfiable(e)) => e.replace(&list, "LIST"),
+                _ => panic!("{words:?} gave no error"),
+            }
+        };
+        assert_eq!(error("empty", &[]), "The word list LIST has no words");
+        assert_eq!(
+            error("too-long", &["extraordinarily", "incomprehensible"]),
+            "The word list LIST has no words of at most 12 characters, raise --max"
+        );
+    }
+
+    #[test]
+    fn title_case_capitalizes_the_first_word_only() {
+        let list = word_list("title-case", &["Apple", "RIVER", "stone", "mountain"]);
+        let opts = parse(&[
+            "--dict-file",
+            &list,
+            "--min",
+            "16",
+            "--max",
+            "30",
+            "dict",
+            "--title-case",
+        ]);
+        for seed in 0..20 {
+            let generated = first(&opts, seed).unwrap();
+            let password = &generated.password;
+            let mut letters = password.chars().filter(|c| c.is_alphabetic());
+            assert!(letters.next().unwrap().is_uppercase(), "{password}");
+            assert!(letters.all(char::is_lowercase), "{password}");
+            assert!(generated.tokens.iter().filter(|t| t.word).count() > 1);
+            for class in required_classes(&opts) {
+                assert!(class.verify(&opts, password), "{class:?} in {password}");
+            }
+        }
+    }
+
+    #[test]
+    fn counts_rng_draws() {
+        let draws_for = |classes: &str| {
+            let opts = parse(&[
+                "--legacy-gen",
+                "--min",
+                "6",
+                "--max",
+                "6",
+                "--classes",
+                classes,
+                "chars",
+            ]);
+            let required = required_classes(&opts);
+            let draws = Rc::new(Cell::new(0));
+            let mut rng =
+                CountingRng::new(rand_chacha::ChaCha20Rng::seed_from_u64(8), draws.clone());
+            let generated = passwords(&opts, &required, &mut rng)
+                .unwrap()
+                .take(50)
+                .count();
+            assert_eq!(generated, 50);
+            draws.get()
+        };
+        // The more classes are required, the more candidates miss one
+        let one = draws_for("+lower");
+        let all = draws_for("+lower+upper+number+symbol");
+        assert!(one > 0);
+        assert!(all > one, "{one} draws for one class, {all} for all");
+    }
+
+    #[test]
+    fn random_characters_avoid_dictionary_words() {
+        let list = word_list("random-words", &["abba", "bab", "Baab"]);
+        let opts = parse(&[
+            "--dict-file",
+            &list,
+            "--reject-random-words",
+            "--lower-chars",
+            "ab",
+            "--classes",
+            "+lower",
+            "chars",
+        ]);
+        let mut rejected = vec![];
+        let mut short_words = 0;
+        for seed in 0..20 {
+            let generated = first(&opts, seed).unwrap();
+            let password = generated.password;
+            assert!(
+                !password.contains("abba") && !password.contains("baab"),
+                "{password}"
+            );
+            // Words shorter than 4 letters are allowed
+            short_words += password.contains("bab") as usize;
+            rejected.extend(generated.rejected);
+        }
+        assert!(rejected.contains(&Rejection::SpellsWord));
+        assert!(short_words > 0);
+    }
+
+    #[test]
+    fn preprocessing_normalizes_the_words() {
+        let list = word_list("preprocess", &["Paris", "café", "Ölberg", "paris"]);
+        let sample = |steps: &[&str]| {
+            let mut args = vec!["--dict-file", &list, "words"];
+            for step in steps {
+                args.extend(["--dict-preprocess", step]);
+            }
+            let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(2);
+            let mut words = sample_words(&parse(&args), 100, &mut rng).unwrap();
+            words.sort_unstable();
+            words.dedup();
+            words
+        };
+        assert_eq!(sample(&["lowercase"]), ["café", "paris", "ölberg"]);
+        assert_eq!(
+            sample(&["strip-accents"]),
+            ["Olberg", "Paris", "cafe", "paris"]
+        );
+        assert_eq!(
+            sample(&["lowercase", "strip-accents"]),
+            ["cafe", "olberg", "paris"]
+        );
+    }
+
+    #[test]
+    fn position_constraints_hold() {
+        let opts = parse(&[
+            "--position-constraint",
+            "-1:number",
+            "--position-constraint",
+            "1:upper-case-letter",
+            "chars",
+        ]);
+        for seed in 0..20 {
+            let password = first(&opts, seed).unwrap().password;
+            assert!(
+                password.ends_with(|c: char| c.is_ascii_digit()),
+                "{password}"
+            );
+            assert!(
+                password.starts_with(|c: char| c.is_ascii_uppercase()),
+                "{password}"
+            );
+        }
+        let opts = parse(&["--position-constraint", "11:number", "chars"]);
+        assert!(matches!(validate(&opts), Err(GenError::Unsatisfiable(_))));
+        let opts = parse(&[
+            "--position-constraint",
+            "2:symbol",
+            "--exclude",
+            "symbol",
+            "chars",
+        ]);
+        assert!(matches!(validate(&opts), Err(GenError::Conflict(_))));
+        assert!("0:number".parse::<PositionConstraint>().is_err());
+    }
+
+    #[test]
+    fn absurd_lengths_hit_the_hard_cap() {
+        let error = |args: &[&str]| match validate(&parse(args)) {
+            Err(GenError::Unsatisfiable(e)) => e,
+            _ => panic!("{args:?} is valid"),
+        };
+        assert_eq!(
+            error(&["--max", "100000000", "chars"]),
+            "A length of 100000000 exceeds the safety limit of 4096 characters, raise \
+             --max-length-hard-cap if this is intended"
+        );
+        assert!(error(&["compact", "--length", "5000"]).starts_with("A length of 5000 "));
+        assert!(error(&["code", "--groups", "2000"]).starts_with("A length of 6000 "));
+        let opts = parse(&["--max-length-hard-cap", "100000", "--max", "5000", "chars"]);
+        assert!(validate(&opts).is_ok());
+    }
+
+    #[test]
+    fn long_words_are_counted() {
+        let list = word_list("long-words", &["ox", "cat", "bee", "mountain", "riverbank"]);
+        let dict = |args: &[&str]| {
+            let base = ["--dict-file", &list, "--min", "10", "--max", "30", "dict"];
+            parse(&[&base[..], args].concat())
+        };
+        let opts = dict(&["--min-long-words", "2", "--long-word-threshold", "6"]);
+        for seed in 0..20 {
+            let generated = first(&opts, seed).unwrap();
+            let long = generated
+                .tokens
+                .iter()
+                .filter(|t| t.word && t.text.len() >= 6)
+                .count();
+            assert!(long >= 2, "{}", generated.password);
+        }
+        // The long words count towards --max-words
+        let opts = dict(&["--min-long-words", "3", "--max-words", "2"]);
+        assert!(matches!(validate(&opts), Err(GenError::Unsatisfiable(_))));
+        let opts = dict(&["--min-long-words", "4", "--long-word-threshold", "8"]);
+        assert!(matches!(validate(&opts), Err(GenError::Unsatisfiable(_))));
+    }
+
+    #[test]
+    fn rejection_histogram_sums_to_the_rejections() {
+        let opts = parse(&["--legacy-gen", "--min", "5", "--no-leading-zero", "chars"]);
+        let required = required_classes(&opts);
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(6);
+        let sample = passwords(&opts, &required, &mut rng)
+            .unwrap()
+            .take(100)
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        let total = sample.iter().map(|g| g.rejected.len()).sum::<usize>();
+        let tries = sample.iter().map(|g| g.tries as usize).sum::<usize>();
+        assert_eq!(total, tries - sample.len());
+        let tally = tally_rejections(sample.into_iter());
+        assert_eq!(tally.iter().map(|(_, count)| count).sum::<usize>(), total);
+        assert!(tally.windows(2).all(|w| w[0].1 >= w[1].1), "{tally:?}");
+        assert!(tally
+            .iter()
+            .any(|(r, _)| matches!(r, Rejection::Missing(_))));
+    }
+
+    #[test]
+    fn prefers_the_strongest_candidate() {
+        let list = word_list(
+            "prefer-strong",
+            &["ox", "cat", "apple", "mountain", "riverbank"],
+        );
+        let base = ["--dict-file", list.as_str(), "--min", "8", "--max", "30"];
+        let plain = parse(&[&base[..], &["dict"]].concat());
+        let strong = parse(&[&base[..], &["--prefer-strong", "5", "dict"]].concat());
+        let required = required_classes(&plain);
+        for seed in 0..5 {
+            let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(seed);
+            let candidates = passwords(&plain, &required, &mut rng)
+                .unwrap()
+                .take(5)
+                .map(Result::unwrap)
+                .collect::<Vec<_>>();
+            let chosen = first(&strong, seed).unwrap();
+            assert!(candidates.iter().all(|c| chosen.entropy >= c.entropy));
+            assert!(candidates.iter().any(|c| c.password == chosen.password));
+            assert_eq!(
+                chosen.tries,
+                candidates.iter().map(|c| c.tries).sum::<u32>()
+            );
+        }
+    }
+
+    #[test]
+    fn single_case_word_lists_give_both_cases() {
+        for (name, words) in [
+            ("lower-case", ["apple", "mountain", "river", "stone"]),
+            ("upper-case", ["APPLE", "MOUNTAIN", "RIVER", "STONE"]),
+        ] {
+            let list = word_list(name, &words);
+            let opts = parse(&["--dict-file", &list, "--classes", "+lower+upper", "dict"]);
+            assert!(validate(&opts).is_ok(), "{name}");
+            for seed in 0..10 {
+                let password = first(&opts, seed).unwrap().password;
+                assert!(
+                    password.chars().any(|c| c.is_lowercase()),
+                    "{name}: {password}"
+                );
+                assert!(
+                    password.chars().any(|c| c.is_uppercase()),
+                    "{name}: {password}"
+                );
+            }
+        }
+        // A first letter outside of the pools cannot become either case
+        let list = word_list("digit-words", &["4ever", "2day"]);
+        let opts = parse(&["--dict-file", &list, "dict"]);
+        let required = required_classes(&opts);
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(0);
+        let error = passwords(&opts, &required, &mut rng).err();
+        assert!(matches!(error, Some(GenError::Unsatisfiable(e)) if e.starts_with("No word of")));
+    }
+
+    #[test]
+    fn turkish_casing() {
+        let upper = |s: &str, locale| {
+            s.chars()
+                .flat_map(|c| to_upper(c, locale))
+                .collect::<String>()
+        };
+        let lower = |s: &str, locale| {
+            s.chars()
+                .flat_map(|c| to_lower(c, locale))
+                .collect::<String>()
+        };
+        let tr = Some(CasingLocale::Tr);
+        assert_eq!(upper("istanbul ırmak", tr), "İSTANBUL IRMAK");
+        assert_eq!(lower("İSTANBUL IRMAK", tr), "istanbul ırmak");
+        assert_eq!(upper("iz", Some(CasingLocale::Az)), "İZ");
+        assert_eq!(upper("istanbul", None), "ISTANBUL");
+        assert_eq!(lower("IRMAK", None), "irmak");
+
+        let list = word_list("turkish", &["istanbul", "IRMAK"]);
+        let opts = parse(&[
+            "--dict-file",
+            &list,
+            "--upper-chars",
+            "ABCÇDEFGĞHIİJKLMNOÖPRSŞTUÜVYZ",
+            "--lower-chars",
+            "abcçdefgğhıijklmnoöprsştuüvyz",
+            "--min",
+            "13",
+            "--max",
+            "13",
+            "--classes",
+            "+lower+upper",
+            "dict",
+            "--title-case",
+            "--casing-locale",
+            "tr",
+        ]);
+        for seed in 0..10 {
+            let password = first(&opts, seed).unwrap().password;
+            assert!(
+                ["İstanbulırmak", "Irmakistanbul"].contains(&password.as_str()),
+                "{password}"
+            );
+        }
+    }
+
+    #[test]
+    fn duplicate_pool_characters_are_removed() {
+        let opts = parse(&[
+            "--symbols",
+            "!!!@@@#",
+            "--number-chars",
+            "1121",
+            "--classes",
+            "+symbol",
+            "chars",
+        ]);
+        assert_eq!(opts.symbols, "!@#");
+        assert_eq!(opts.number_chars, "12");
+        let required = required_classes(&opts);
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(1);
+        let sample = passwords(&opts, &required, &mut rng)
+            .unwrap()
+            .take(300)
+            .map(|generated| generated.unwrap().password)
+            .collect::<String>();
+        let total = sample.chars().count() as f64;
+        for symbol in ['!', '@', '#'] {
+            let share = sample.chars().filter(|c| *c == symbol).count() as f64 / total;
+            assert!((share - 1.0 / 3.0).abs() < 0.03, "{symbol}: {share}");
+        }
+    }
+
+    #[test]
+    fn resolved_pools_reflect_the_options() {
+        let opts = parse(&[
+            "--exclude",
+            "upper-case-letter",
+            "--number-chars",
+            "2345",
+            "--symbols",
+            "!#",
+            "--emoji",
+            "🔑",
+            "chars",
+        ]);
+        let pools = resolve_pools(&opts);
+        assert_eq!(
+            pools.of(Constraint::LowerCaseLetter).concat(),
+            LOWER_CASE_LETTERS
+        );
+        assert!(pools.of(Constraint::UpperCaseLetter).is_empty());
+        assert_eq!(pools.of(Constraint::Number), ["2", "3", "4", "5"]);
+        assert_eq!(pools.of(Constraint::Symbol), ["!", "#", "🔑"]);
+        let pools = resolve_pools(&parse(&["--letters-only", "chars"]));
+        assert!(pools.of(Constraint::Number).is_empty() && pools.of(Constraint::Symbol).is_empty());
+    }
+
+    #[test]
+    fn digits_in_words_are_not_random_digits() {
+        let list = word_list("random-digits", &["route66", "area51", "mountain"]);
+        let opts = parse(&[
+            "--dict-file",
+            &list,
+            "--max",
+            "30",
+            "--min-random-digits",
+            "3",
+            "dict",
+        ]);
+        assert!(validate(&opts).is_ok());
+        let mut digit_words = 0;
+        for seed in 0..20 {
+            let generated = first(&opts, seed).unwrap();
+            let random_digits = generated
+                .tokens
+                .iter()
+                .filter(|t| !t.word && t.class == Constraint::Number)
+                .count();
+            assert!(random_digits >= 3, "{}", generated.password);
+            digit_words += generated
+                .tokens
+                .iter()
+                .filter(|t| t.word && t.text.contains(|c: char| c.is_ascii_digit()))
+                .count();
+        }
+        assert!(digit_words > 0);
+        let opts = parse(&["--min-random-digits", "2", "--exclude", "number", "chars"]);
+        assert!(matches!(validate(&opts), Err(GenError::Conflict(_))));
+    }
+
+    #[test]
+    fn exact_dict_lengths() {
+        let list = word_list("exact", &["apple", "mountain", "river"]);
+        let exact = |args: &[&str]| {
+            let base = [
+                "--dict-file",
+                list.as_str(),
+                "--min",
+                "12",
+                "--max",
+                "12",
+                "dict",
+            ];
+            parse(&[&base[..], args].concat())
+        };
+        // The words and one number and symbol can make 12 characters
+        let opts = exact(&[]);
+        for seed in 0..10 {
+            assert_eq!(first(&opts, seed).unwrap().password.len(), 12);
+        }
+        let opts = exact(&["--word-transform", "drop-vowels"]);
+        for seed in 0..10 {
+            match first(&opts, seed) {
+                Ok(generated) => assert_eq!(generated.password.len(), 12),
+                Err(e) => assert!(matches!(e, GenError::Exhausted { .. }), "{e}"),
+            }
+        }
+        let opts = exact(&["--pad-with-chars"]);
+        for seed in 0..10 {
+            assert_eq!(first(&opts, seed).unwrap().password.len(), 12);
+        }
+        // Two words for both letter classes are already too long
+        let list = word_list("exact-unreachable", &["mountain"]);
+        let opts = parse(&["--dict-file", &list, "--min", "12", "--max", "12", "dict"]);
+        let required = required_classes(&opts);
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(0);
+        let error = passwords(&opts, &required, &mut rng).err();
+        assert!(
+            matches!(&error, Some(GenError::Unsatisfiable(e)) if e.ends_with("--pad-with-chars")),
+            "{error:?}"
+        );
+    }
+
+    #[test]
+    fn letter_runs_are_limited() {
+        let opts = parse(&["--max-consecutive-consonants", "3", "chars"]);
+        assert_eq!(
+            secondary_rejection(&opts, "xbcdfg1A", &[]),
+            Some(Rejection::ConsonantRun)
+        );
+        assert_eq!(secondary_rejection(&opts, "bcd1fgh", &[]), None);
+        for seed in 0..20 {
+            let password = first(&opts, seed).unwrap().password;
+            assert!(longest_letter_run(&password, false) <= 3, "{password}");
+        }
+        let opts = parse(&["--max-consecutive-vowels", "1", "chars"]);
+        assert_eq!(
+            secondary_rejection(&opts, "xAe1", &[]),
+            Some(Rejection::VowelRun)
+        );
+        for seed in 0..20 {
+            let password = first(&opts, seed).unwrap().password;
+            assert!(longest_letter_run(&password, true) <= 1, "{password}");
+        }
+    }
+
+    #[test]
+    fn mixed_case_adds_both_letter_classes() {
+        let opts = parse(&["--require-mixed-case", "--require", "number", "chars"]);
+        assert_eq!(
+            required_classes(&opts),
+            [
+                Constraint::Number,
+                Constraint::LowerCaseLetter,
+                Constraint::UpperCaseLetter
+            ]
+        );
+        for seed in 0..20 {
+            let password = first(&opts, seed).unwrap().password;
+            assert!(
+                password.chars().any(|c| c.is_ascii_lowercase()),
+                "{password}"
+            );
+            assert!(
+                password.chars().any(|c| c.is_ascii_uppercase()),
+                "{password}"
+            );
+        }
+        // All classes are required anyway without --require
+        let opts = parse(&["--require-mixed-case", "chars"]);
+        assert_eq!(required_classes(&opts).len(), 4);
+    }
+
+    #[test]
+    fn distinct_symbols_are_required() {
+        let opts = parse(&["--min-distinct-symbols", "4", "chars"]);
+        assert!(validate(&opts).is_ok());
+        for seed in 0..20 {
+            let password = first(&opts, seed).unwrap().password;
+            assert!(distinct_symbols(&opts, &password) >= 4, "{password}");
+        }
+        // Repeating a symbol does not count
+        assert_eq!(distinct_symbols(&opts, "a!!!b#"), 2);
+        let opts = parse(&["--symbols", "!@#", "--min-distinct-symbols", "4", "chars"]);
+        assert!(matches!(validate(&opts), Err(GenError::Unsatisfiable(_))));
+    }
+
+    #[test]
+    fn dict_words_fit_on_the_first_try() {
+        let list = word_list(
+            "budget",
+            &[
+                "ox",
+                "cat",
+                "bird",
+                "apple",
+                "stone",
+                "river",
+                "planet",
+                "mountain",
+                "watermelon",
+            ],
+        );
+        let opts = parse(&["--dict-file", &list, "--min", "16", "--max", "20", "dict"]);
+        let required = required_classes(&opts);
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(12);
+        let first_tries = passwords(&opts, &required, &mut rng)
+            .unwrap()
+            .take(200)
+            .filter(|generated| generated.as_ref().unwrap().tries == 1)
+            .count();
+        assert!(first_tries >= 190, "{first_tries} of 200");
+    }
+
+    #[test]
+    fn rare_words_are_dropped() {
+        let path = std::env::temp_dir().join("gen-pw-test-frequencies.txt");
+        std::fs::write(
+            &path,
+            "the 5000\nriver 120\nplanet 80\nzymurgy 2\nquixotic 9\n",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+        let sample = |threshold: &str| {
+            let opts = parse(&[
+                "--dict-file",
+                path,
+                "--dict-min-frequency",
+                threshold,
+                "words",
+            ]);
+            let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(3);
+            let mut words = sample_words(&opts, 100, &mut rng).unwrap();
+            words.sort_unstable();
+            words.dedup();
+            words
+        };
+        assert_eq!(sample("10"), ["planet", "river", "the"]);
+        assert_eq!(sample("100"), ["river", "the"]);
+        assert_eq!(sample("0").len(), 5);
+    }
+
+    #[test]
+    fn audit_lines_leave_out_the_password() {
+        let opts = parse(&["chars"]);
+        let generated = first(&opts, 5).unwrap();
+        let time = std::time::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let line = audit_line(&opts, &generated, time);
+        assert_eq!(
+            line,
+            format!(
+                "{{\"time\":1700000000,\"command\":\"chars\",\"length\":{},\"tries\":{},\
+                 \"entropy\":{:.1}}}",
+                generated.password.len(),
+                generated.tries,
+                generated.entropy
+            )
+        );
+        assert!(!line.contains(&generated.password));
+    }
+
+    #[test]
+    fn tiny_word_pools_fail_the_entropy_guard() {
+        let list = word_list("entropy-guard", &["apple", "mountain", "river", "stone"]);
+        let guarded = |bits: &str| {
+            let opts = parse(&["--dict-file", &list, "dict", "--min-per-word-entropy", bits]);
+            let required = required_classes(&opts);
+            let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(0);
+            let error = passwords(&opts, &required, &mut rng).err();
+            error
+        };
+        // Four words give 2 bits each
+        assert!(matches!(
+            guarded("5"),
+            Some(GenError::Unsatisfiable(e)) if e.contains("only adds 2.0 bits")
+        ));
+        assert!(guarded("2").is_none());
+    }
+
+    #[test]
+    fn words_do_not_repeat_within_the_window() {
+        let list = word_list("repeat-window", &["ox", "cat", "bee"]);
+        let opts = parse(&[
+            "--dict-file",
+            &list,
+            "--min",
+            "14",
+            "--max",
+            "20",
+            "dict",
+            "--no-repeat-within",
+            "2",
+        ]);
+        let mut repeated_later = false;
+        for seed in 0..20 {
+            let generated = first(&opts, seed).unwrap();
+            let words = generated
+                .tokens
+                .iter()
+                .filter(|t| t.word)
+                .map(|t| t.text.to_lowercase())
+                .collect::<Vec<_>>();
+            for (i, word) in words.iter().enumerate() {
+                let window = &words[i.saturating_sub(2)..i];
+                assert!(!window.contains(word), "{}", generated.password);
+            }
+            // Words come back once they are out of the window
+            repeated_later |= words.windows(4).any(|w| w[0] == w[3]);
+        }
+        assert!(repeated_later);
+    }
+
+    #[test]
+    fn capabilities_list_the_classes_and_commands() {
+        let json = capabilities(Opts::command());
+        assert!(json.contains(
+            "\"constraints\":[\"lower-case-letter\",\"upper-case-letter\",\"number\",\"symbol\"]"
+        ));
+        for command in ["dict", "chars"] {
+            assert!(
+                json.contains(&format!("{{\"name\":\"{command}\",")),
+                "{command}"
+            );
+        }
+        assert!(json.contains("\"--min\""));
+        // Hidden developer options are left out
+        assert!(!json.contains("--analyze-bias"));
+        assert!(!json.contains("\"name\":\"help\""));
+    }
+
+    #[test]
+    fn starts_unambiguously_when_required() {
+        let opts = parse(&["--require-non-ambiguous-start", "chars"]);
+        for seed in 0..30 {
+            let password = first(&opts, seed).unwrap().password;
+            let start = password.chars().next().unwrap();
+            assert!(
+                start.is_ascii_alphanumeric() && !is_lookalike(start),
+                "{password}"
+            );
+        }
+        for rejected in ["l1x", "0abc", "!abc", "Oak", ""] {
+            assert!(!starts_unambiguously(&opts, rejected), "{rejected}");
+        }
+        assert!(starts_unambiguously(&opts, "k1l"));
+        // Nothing unambiguous to start with
+        let opts = parse(&[
+            "--require-non-ambiguous-start",
+            "--classes",
+            "+number+symbol",
+            "--number-chars",
+            "0125",
+            "chars",
+        ]);
+        assert!(validate(&opts).is_err());
+    }
+
+    #[test]
+    fn symbol_tier_limits_hold() {
+        let opts = parse(&[
+            "--symbols",
+            "!@#~^|",
+            "--symbol-tier",
+            "common=!@#",
+            "--symbol-tier",
+            "uncommon=~^|",
+            "--symbol-tier-min",
+            "common=1",
+            "--symbol-tier-max",
+            "uncommon=1",
+            "--symbol-probability",
+            "0.5",
+            "chars",
+        ]);
+        assert!(validate(&opts).is_ok());
+        let count = |s: &str, tier: &str| s.chars().filter(|c| tier.contains(*c)).count();
+        for seed in 0..20 {
+            let password = first(&opts, seed).unwrap().password;
+            assert!(count(&password, "!@#") >= 1, "{password}");
+            assert!(count(&password, "~^|") <= 1, "{password}");
+        }
+        assert!(symbol_tiers_hold(&opts, "ab!~"));
+        assert!(!symbol_tiers_hold(&opts, "ab~"));
+        assert!(!symbol_tiers_hold(&opts, "a!~^"));
+        assert!("common".parse::<SymbolTier>().is_err());
+    }
+
+    #[test]
+    fn preserve_case_keeps_cased_words() {
+        let words = ["NASA", "iPhone", "river", "Paris"];
+        let list = word_list("preserve-case", &words);
+        let opts = parse(&[
+            "--dict-file",
+            &list,
+            "--min",
+            "14",
+            "--max",
+            "24",
+            "dict",
+            "--preserve-case",
+        ]);
+        let mut kept = HashSet::new();
+        for seed in 0..30 {
+            let generated = first(&opts, seed).unwrap();
+            for class in required_classes(&opts) {
+                assert!(
+                    class.verify(&opts, &generated.password),
+                    "{}",
+                    generated.password
+                );
+            }
+            for token in generated.tokens.iter().filter(|t| t.word) {
+                // At most the first letter changes its case
+                let original = words
+                    .iter()
+                    .find(|w| {
+                        w[1..] == token.text[1..] && w[..1].eq_ignore_ascii_case(&token.text[..1])
+                    })
+                    .unwrap_or_else(|| panic!("{} is not from the list", token.text));
+                if *original == token.text {
+                    kept.insert(token.text.clone());
+                }
+            }
+        }
+        // Each word has letters of both cases or is picked for its own case
+        // at some point
+        assert_eq!(kept.len(), words.len(), "{kept:?}");
+    }
+
+    #[test]
+    fn rejects_the_username() {
+        assert!(contains_username("xxAdMinyy", "admin", false));
+        assert!(!contains_username("xx@dm1nyy", "admin", false));
+        assert!(contains_username("xx@dm1nyy", "admin", true));
+        assert!(contains_username("x4DM1N", "Admin", true));
+
+        let opts = parse(&[
+            "--username",
+            "abba",
+            "--lower-chars",
+            "ab",
+            "--upper-chars",
+            "AB",
+            "--classes",
+            "+lower+upper",
+            "chars",
+        ]);
+        let mut rejected = vec![];
+        for seed in 0..20 {
+            let generated = first(&opts, seed).unwrap();
+            assert!(
+                !generated.password.to_lowercase().contains("abba"),
+                "{}",
+                generated.password
+            );
+            rejected.extend(generated.rejected);
+        }
+        assert!(rejected.contains(&Rejection::ContainsUsername));
+        let opts = parse(&["--username", "", "chars"]);
+        assert!(matches!(validate(&opts), Err(GenError::Conflict(_))));
+    }
+
+    #[test]
+    fn benchmark_reports_a_positive_rate() {
+        let duration = Duration::from_millis(50);
+        let throughput = benchmark(&parse(&["chars"]), duration).unwrap();
+        assert!(throughput.passwords > 0);
+        assert!(throughput.elapsed >= duration);
+        assert!(throughput.per_second() > 0.0);
+        assert!(throughput.mean_tries >= 1.0);
+        // Even a zero duration measures one password
+        let throughput = benchmark(&parse(&["chars"]), Duration::ZERO).unwrap();
+        assert_eq!(throughput.passwords, 1);
+    }
+}
@@ -1,252 +1,506 @@
-extern crate clap;
-extern crate either;
-extern crate rand;
 #[macro_use]
 extern crate log;
 extern crate simple_logger;
 
-use std::{ops::Range, process::Stdio, str::FromStr};
+use std::{
+    cell::Cell,
+    ffi::OsString,
+    io::{IsTerminal, Write},
+    path::Path,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
-use clap::{Parser, Subcommand, ValueEnum};
-use either::Either;
+use clap::{CommandFactory, Parser, ValueEnum};
+use gen_pw::{
+    analyze_bias, benchmark, capabilities, completions, config, crack_time, humanize_duration,
+    interrupt, keyboard_warnings, keyring, lookalike_legend, render_template, selftest,
+    shell_quote, summarize, tally_rejections, to_json, Command, Constraint, CountingRng, GenError,
+    Generated, Opts, OutputFormat,
+};
 
-use rand::{prelude::Distribution, seq::SliceRandom};
+/// How often `--progress` updates the count
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+/// Passwords generated per configuration by `compare`
+const COMPARE_SAMPLES: usize = 500;
 
-#[derive(Subcommand)]
-enum Command {
-    /// Select the letter portion of the password by sampling words from a dictionary
-    Dict {
-        #[clap(long, env, default_value = "en")]
-        language: String,
-    },
-    /// Select the letter portion of the password by just randomly selecting
-    /// (a-z) letters.
-    Chars,
+/// `generated`, exiting if the generator gave up.
+fn or_exit(generated: Result<Generated, GenError>) -> Generated {
+    generated.unwrap_or_else(|e| exit_with(e))
 }
 
-#[derive(Clone, ValueEnum, PartialEq, Copy, Debug)]
-enum Constraint {
-    LowerCaseLetter,
-    UpperCaseLetter,
-    Number,
-    Symbol,
-}
-
-impl Command {
-    fn weights(&self, constraint: Constraint) -> u8 {
-        match self {
-            Command::Chars => 1,
-            Command::Dict { .. } => match constraint {
-                Constraint::LowerCaseLetter | Constraint::UpperCaseLetter => 2,
-                Constraint::Number | Constraint::Symbol => 1,
-            },
+/// Report `e` in the style of clap's own errors and exit.
+fn exit_with(e: GenError) -> ! {
+    let kind = match e {
+        GenError::Exhausted { .. } => {
+            // Not a usage error, the options may just be unlucky
+            eprintln!("error: {e}");
+            std::process::exit(1);
         }
-    }
+        GenError::Conflict(_) => clap::ErrorKind::ArgumentConflict,
+        GenError::Io(_) => clap::ErrorKind::Io,
+        _ => clap::ErrorKind::ValueValidation,
+    };
+    Opts::command().error(kind, e).exit()
 }
 
-impl Constraint {
-    fn verify(self, opts: &Opts, s: &str) -> bool {
-        s.chars().any(|c| match self {
-            Constraint::LowerCaseLetter => LOWER_CASE_LETTERS.contains(&c),
-            Constraint::UpperCaseLetter => UPPER_CASE_LETTERS.contains(&c),
-            Constraint::Number => NUMBERS.contains(&c),
-            Constraint::Symbol => opts.symbols.contains(c),
-        })
+/// The options of the configuration file `path` for `compare` and
+/// `benchmark`, combined with the options in `args`. Exits if the file does
+/// not select a command that generates passwords.
+fn load_config(args: &[OsString], path: &Path) -> Opts {
+    let args = config::merge_args(
+        config::with_config(args, path, &Opts::command()),
+        &Opts::command(),
+    )
+    .unwrap_or_else(|e| Opts::command().error(clap::ErrorKind::Io, e).exit());
+    let mut opts = Opts::try_parse_from(args).unwrap_or_else(|e| match e.kind() {
+        clap::ErrorKind::MissingSubcommand
+        | clap::ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand => exit_with(
+            GenError::Conflict(format!("{} does not set a command", path.display())),
+        ),
+        _ => Opts::command()
+            .error(
+                clap::ErrorKind::ValueValidation,
+                format!("In {}: {e}", path.display()),
+            )
+            .exit(),
+    });
+    if let Command::Compare { .. }
+    | Command::Benchmark { .. }
+    | Command::SelfTest
+    | Command::Completions { .. }
+    | Command::Capabilities = opts.command
+    {
+        exit_with(GenError::Conflict(format!(
+            "{} must select a command that generates passwords",
+            path.display()
+        )));
     }
+    opts.normalize().unwrap_or_else(|e| {
+        exit_with(GenError::Unsatisfiable(format!(
+            "In {}: {e}",
+            path.display()
+        )))
+    });
+    opts
 }
 
-impl FromStr for Constraint {
-    type Err = String;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        <Self as ValueEnum>::from_str(s, true)
-    }
+/// Print the `compare` table for the configuration files `paths`, evaluated
+/// with the options in `args`.
+fn compare(args: &[OsString], paths: [&Path; 2]) {
+    let summaries = paths.map(|path| {
+        summarize(&load_config(args, path), COMPARE_SAMPLES).unwrap_or_else(|e| {
+            exit_with(GenError::Unsatisfiable(format!(
+                "In {}: {e}",
+                path.display()
+            )))
+        })
+    });
+    let [first, second] = &summaries;
+    let [a, b] = paths.map(|path| path.display().to_string());
+    let mut out = std::io::stdout().lock();
+    check_write(writeln!(out, "{:<24} {a:>16} {b:>16}", ""));
+    check_write(writeln!(
+        out,
+        "{:<24} {:>16.1} {:>16.1}",
+        "mean entropy (bits)", first.mean_entropy, second.mean_entropy
+    ));
+    check_write(writeln!(
+        out,
+        "{:<24} {:>16.1} {:>16.1}",
+        "lowest entropy (bits)", first.min_entropy, second.min_entropy
+    ));
+    check_write(writeln!(
+        out,
+        "{:<24} {:>16.2} {:>16.2}",
+        "mean tries", first.mean_tries, second.mean_tries
+    ));
+    check_write(writeln!(
+        out,
+        "{:<24} {:>16} {:>16}",
+        "length",
+        format!("{}-{}", first.min_len, first.max_len),
+        format!("{}-{}", second.min_len, second.max_len)
+    ));
 }
 
-const LOWER_CASE_LETTERS: Range<char> = 'a'..'z';
-const UPPER_CASE_LETTERS: Range<char> = 'A'..'Z';
-const NUMBERS: Range<char> = '0'..'9';
-const SYMBOLS: &'static str = "-_/[]{}()*&^%$#@.!?=+:;|~";
-
-/// Generate strong passwords by sampling words form an aspell dictionary or
-/// just letters.
-#[derive(Parser)]
-#[clap(author, version, about)]
-struct Opts {
-    /// Minimal length of the password
-    #[clap(long, default_value = "10")]
-    min: usize,
-    /// Maximal length of the password
-    #[clap(long, default_value = "20")]
-    max: usize,
-    /// Require this constraint be fulfilled, if left empty all constraints are required.
-    #[clap(long)]
-    require: Vec<Constraint>,
-    /// Exclude this constraint. Overwrites both default and elements in `require`
-    #[clap(long)]
-    exclude: Vec<Constraint>,
-    /// Characters to use as the valid symbols
-    #[clap(long, default_value = SYMBOLS)]
-    symbols: String,
-    #[clap(long, default_value = "1000")]
-    tries: usize,
-    #[clap(long, short = 'v')]
-    verbose: bool,
-    #[clap(long)]
-    debug: bool,
-    #[clap(subcommand)]
-    command: Command,
+/// Print the `i`th output line.
+fn write_line(out: &mut impl Write, opts: &Opts, i: usize, line: &str) {
+    check_write(if !opts.no_newline {
+        writeln!(out, "{line}")
+    } else if i == 0 {
+        write!(out, "{line}")
+    } else {
+        // Only separate the passwords, without a newline after the last
+        write!(out, "\n{line}")
+    });
 }
 
-fn do_gen<F: Fn(&mut String, &mut rand::rngs::ThreadRng, bool) -> Option<()>>(
-    opts: &Opts,
-    required: &[Constraint],
-    pick_letters: F,
-) -> (String, u32) {
-    let ref mut rng = rand::thread_rng();
-    let weights = required
-        .iter()
-        .copied()
-        .map(|constraint| opts.command.weights(constraint));
-    let dist = rand::distributions::WeightedIndex::new(weights).unwrap();
-    std::iter::repeat_with(|| {
-        let mut s = String::new();
-        while s.len() < opts.min {
-            match required[dist.sample(rng)] {
-                Constraint::LowerCaseLetter => pick_letters(&mut s, rng, true)?,
-                Constraint::UpperCaseLetter => pick_letters(&mut s, rng, false)?,
-                Constraint::Symbol => s.push(
-                    <std::str::Chars as rand::seq::IteratorRandom>::choose(
-                        opts.symbols.chars(),
-                        rng,
-                    )
-                    .unwrap(),
-                ),
-                Constraint::Number => s.push(
-                    <std::ops::Range<char> as rand::seq::IteratorRandom>::choose(
-                        NUMBERS.clone(),
-                        rng,
-                    )
-                    .unwrap(),
-                ),
-            }
-        }
-        let judgement = s.len() <= opts.max && !required.iter().any(|c| !c.verify(opts, &s));
-        if !judgement {
-            debug!(
-                "Rejecting {s}: {} {:?} {} and {:?}",
-                s.len(),
-                s.len().cmp(&opts.max),
-                opts.max,
-                required
-                    .iter()
-                    .map(|c| (c, c.verify(opts, &s)))
-                    .collect::<Vec<_>>()
-            );
-        }
-        judgement.then_some(s)
-    })
-    .take(opts.tries)
-    .find_map({
-        let mut actual_tries = 0;
-        move |s| {
-            actual_tries += 1;
-            s.map(|s| (s, actual_tries))
-        }
-    })
-    .expect(&format!(
-        "Could not find a satisfactory string in {} tries",
-        opts.tries
-    ))
+/// Exit quietly if stdout was closed early, e.g. by `head`, and with an
+/// error if writing failed otherwise.
+fn check_write(result: std::io::Result<()>) {
+    match result {
+        Ok(()) => {}
+        // The exit code of a process killed by SIGPIPE
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => std::process::exit(141),
+        Err(e) => Opts::command()
+            .error(
+                clap::ErrorKind::Io,
+                format!("Could not write the output: {e}"),
+            )
+            .exit(),
+    }
 }
 
 fn main() {
-    let ref opts = Opts::parse();
-
-    simple_logger::SimpleLogger::default().with_level(
-        if opts.debug {
+    let started = Instant::now();
+    let raw_args = std::env::args_os().collect::<Vec<_>>();
+    let args = config::merge_args(raw_args.clone(), &Opts::command())
+        .unwrap_or_else(|e| Opts::command().error(clap::ErrorKind::Io, e).exit());
+    // `--help` and `--version` print to stdout and exit here, before the
+    // logger is set up, so nothing else ends up in their output
+    let mut opts = Opts::parse_from(args);
+    simple_logger::SimpleLogger::default()
+        .with_level(if opts.debug {
             log::LevelFilter::Debug
         } else if opts.verbose {
             log::LevelFilter::Info
+        } else if opts.quiet {
+            log::LevelFilter::Error
         } else {
             log::LevelFilter::Warn
         })
         .init()
         .unwrap();
-    if opts.debug {
+    if opts.debug && !opts.banner_off {
         info!("Running with debug");
     }
 
-    let ref required = if opts.require.is_empty() {
-        Either::Left(Constraint::value_variants().iter())
+    if let Err(e) = opts.normalize() {
+        exit_with(e);
+    }
+    let opts = &opts;
+    // Every write goes through `check_write`, so closing stdout early, e.g.
+    // with `head`, does not panic
+    let mut out = std::io::stdout().lock();
+
+    if let Command::Compare { first, second } = &opts.command {
+        compare(&raw_args, [first, second]);
+        return;
+    }
+
+    if let Command::Benchmark {
+        config,
+        duration_secs,
+    } = &opts.command
+    {
+        let loaded = config.as_deref().map(|path| load_config(&raw_args, path));
+        let throughput = benchmark(
+            loaded.as_ref().unwrap_or(opts),
+            Duration::from_secs(*duration_secs),
+        )
+        .unwrap_or_else(|e| exit_with(e));
+        check_write(writeln!(
+            out,
+            "{:<16} {}",
+            "passwords", throughput.passwords
+        ));
+        check_write(writeln!(
+            out,
+            "{:<16} {:.2?}",
+            "elapsed", throughput.elapsed
+        ));
+        check_write(writeln!(
+            out,
+            "{:<16} {:.1}",
+            "passwords/s",
+            throughput.per_second()
+        ));
+        check_write(writeln!(
+            out,
+            "{:<16} {:.2}",
+            "mean tries", throughput.mean_tries
+        ));
+        return;
+    }
+
+    if let Command::Completions { shell } = opts.command {
+        check_write(write!(
+            out,
+            "{}",
+            completions::generate(shell, Opts::command(), env!("CARGO_PKG_NAME"))
+        ));
+        return;
+    }
+
+    if let Command::Capabilities = opts.command {
+        check_write(writeln!(out, "{}", capabilities(Opts::command())));
+        return;
+    }
+
+    if let Command::SelfTest = opts.command {
+        let (checks, skipped_dict) = selftest::run();
+        let mut failed = false;
+        for check in &checks {
+            match &check.result {
+                Ok(()) => check_write(writeln!(out, "ok      {}", check.name)),
+                Err(e) => {
+                    failed = true;
+                    check_write(writeln!(out, "FAILED  {}: {e}", check.name));
+                }
+            }
+        }
+        if skipped_dict {
+            check_write(writeln!(
+                out,
+                "Skipped the dictionary cases, aspell has no English dictionary"
+            ));
+        }
+        std::process::exit(failed as i32);
+    }
+
+    if let Err(e) = gen_pw::validate(opts) {
+        exit_with(e);
+    }
+    if opts.dry_parse {
+        check_write(writeln!(out, "OK"));
+        return;
+    }
+    let required = &gen_pw::required_classes(opts);
+    if opts.per_class_pools_report {
+        let pools = gen_pw::resolve_pools(opts);
+        for class in Constraint::value_variants() {
+            let pool = pools.of(*class);
+            let name = class.to_possible_value().unwrap().get_name();
+            if pool.is_empty() {
+                eprintln!("{name:<18} (not picked)");
+            } else {
+                eprintln!("{name:<18} {} ({} characters)", pool.concat(), pool.len());
+            }
+        }
+    }
+
+    let (rng, seed) = gen_pw::make_rng(opts);
+    if let Some(seed) = seed.filter(|_| opts.show_seed) {
+        eprintln!("Seed: {seed}");
+    }
+    let draws = Rc::new(Cell::new(0));
+    let mut rng: Box<dyn rand::RngCore> = if opts.sample_rng_report {
+        Box::new(CountingRng::new(rng, draws.clone()))
     } else {
-        Either::Right(opts.require.iter())
-    }
-    .cloned()
-    .into_iter()
-    .filter(|i| !opts.exclude.contains(i))
-    .collect::<Vec<_>>();
-    assert!(!required.is_empty());
-    assert!(opts.min <= opts.max);
-    assert!(opts.min > required.len());
-
-    let (result, tries) = match &opts.command {
-        Command::Chars => do_gen(opts, required, |s, rng, is_lowercase| {
-            s.push(
-                <std::ops::Range<char> as rand::seq::IteratorRandom>::choose(
-                    if is_lowercase {
-                        LOWER_CASE_LETTERS
-                    } else {
-                        UPPER_CASE_LETTERS
-                    },
-                    rng,
-                )
-                .unwrap(),
-            );
-            Some(())
-        }),
-        Command::Dict { language } => {
-            let dump = std::process::Command::new("aspell")
-                .args(&["-d", language.as_str(), "dump", "master"])
-                .stdout(Stdio::piped())
-                .spawn()
-                .unwrap();
-            let expanded = std::process::Command::new("aspell")
-                .args(&["-l", language.as_str(), "expand"])
-                .stdin(dump.stdout.unwrap())
-                .output()
-                .unwrap();
-            let s = String::from_utf8(expanded.stdout).unwrap();
-            let words = s
-                .split(char::is_whitespace)
-                .filter(|s| !s.contains('\'') && !s.is_empty() && !s.len() > opts.max)
-                .collect::<Vec<_>>();
-
-            assert!(words
-                .iter()
-                .any(|w| w.len() >= opts.min && w.len() <= opts.max));
-
-            assert!(words.len() > 0);
-
-            do_gen(opts, &required, |s, rng, is_lowercase| {
-                let word = *std::iter::from_fn(|| words.choose(rng))
-                    .filter(|w| w.len() + s.len() <= opts.max)
-                    .next()?;
-                let mut chars = word.chars();
-                let first = chars.next().unwrap();
-
-                s.extend(
-                    if is_lowercase {
-                        Either::Left(first.to_lowercase())
-                    } else {
-                        Either::Right(first.to_uppercase())
-                    }
-                    .into_iter()
-                    .chain(chars),
-                );
-                Some(())
+        rng
+    };
+    let rng = &mut *rng;
+
+    if let Command::Words { count, .. } = opts.command {
+        for word in gen_pw::sample_words(opts, count, rng).unwrap_or_else(|e| exit_with(e)) {
+            check_write(writeln!(out, "{word}"));
+        }
+        return;
+    }
+
+    let loading = Instant::now();
+    let mut passwords = gen_pw::passwords(opts, required, rng).unwrap_or_else(|e| exit_with(e));
+    let loading = loading.elapsed();
+
+    if let Some(samples) = opts.analyze_bias {
+        let report = analyze_bias(
+            opts,
+            required,
+            passwords.by_ref().take(samples).map(or_exit),
+        );
+        check_write(writeln!(
+            out,
+            "{:<20} {:>10} {:>10}",
+            "class", "observed", "expected"
+        ));
+        for (class, observed, expected) in report {
+            check_write(writeln!(
+                out,
+                "{class:<20} {observed:>10.4} {expected:>10.4}"
+            ));
+        }
+        return;
+    }
+
+    if let Some(samples) = opts.explain_rejections {
+        let tally = tally_rejections(passwords.by_ref().take(samples).map(or_exit));
+        let total = tally.iter().map(|(_, count)| count).sum::<usize>();
+        check_write(writeln!(
+            out,
+            "{:<26} {:>10} {:>10}",
+            "reason", "rejections", "share"
+        ));
+        for (reason, count) in tally {
+            check_write(writeln!(
+                out,
+                "{:<26} {count:>10} {:>10.4}",
+                reason.to_string(),
+                count as f64 / total as f64
+            ));
+        }
+        check_write(writeln!(out, "{:<26} {total:>10}", "total"));
+        return;
+    }
+
+    if let Some(n) = opts.preview.or(opts.choose_from) {
+        for (i, Generated { password, .. }) in passwords.by_ref().take(n).map(or_exit).enumerate() {
+            check_write(writeln!(out, "{}. {password}", i + 1));
+        }
+        if let Some(token) = seed.filter(|_| opts.choose_from.is_some()) {
+            eprintln!("Token: {token}");
+        }
+        return;
+    }
+    if let Some(k) = opts.choose {
+        // The same seed and options yield the same candidates in order
+        passwords.by_ref().take(k - 1).map(or_exit).for_each(drop);
+    }
+
+    if opts.count > 1 {
+        interrupt::install_handler();
+    }
+    let show_progress = opts.progress && !opts.quiet && std::io::stderr().is_terminal();
+    let mut last_progress = Instant::now();
+    let mut out = std::io::BufWriter::new(out);
+    let mut audit_log = opts.audit_log.as_ref().map(|path| {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|e| {
+                exit_with(GenError::Io(format!(
+                    "Could not open the audit log {}: {e}",
+                    path.display()
+                )))
             })
+    });
+    let generating = Instant::now();
+    let mut sorted = vec![];
+    for (i, generated) in passwords.by_ref().take(opts.count).enumerate() {
+        if interrupt::interrupted() {
+            break;
         }
-    };
-    info!("Needed {tries} tries");
-    println!("{result}");
+        let generated = generated.unwrap_or_else(|e| {
+            // Keep the passwords found so far
+            check_write(out.flush());
+            exit_with(e)
+        });
+        let formatted = match &opts.output_template {
+            // The template was checked by validate
+            Some(template) => Some(render_template(template, &generated).unwrap()),
+            None if opts.format == OutputFormat::Json => Some(to_json(opts, &generated)),
+            None => None,
+        };
+        let audit = opts
+            .audit_log
+            .is_some()
+            .then(|| gen_pw::audit_line(opts, &generated, std::time::SystemTime::now()));
+        let Generated {
+            password,
+            tries,
+            entropy,
+            tokens,
+            ..
+        } = generated;
+        info!("Needed {tries} tries");
+        if opts.sample_rng_report {
+            eprintln!("RNG draws: {}", draws.replace(0));
+        }
+        if opts.explain {
+            for token in &tokens {
+                eprintln!("{:<20} {}", token.text, token.source());
+            }
+        }
+        if opts.annotate {
+            for line in lookalike_legend(&password) {
+                eprintln!("{line}");
+            }
+        }
+        if let Some(layout) = opts.keyboard_layout {
+            for line in keyboard_warnings(layout, &password) {
+                eprintln!("{line}");
+            }
+        }
+        if opts.show_cracktime {
+            eprintln!("Estimated entropy: {entropy:.1} bits");
+            eprintln!(
+                "Offline attack ({} guesses/s): {}",
+                opts.offline_guesses_per_sec,
+                humanize_duration(crack_time(entropy, opts.offline_guesses_per_sec))
+            );
+            eprintln!(
+                "Online attack ({} guesses/s): {}",
+                opts.online_guesses_per_sec,
+                humanize_duration(crack_time(entropy, opts.online_guesses_per_sec))
+            );
+        }
+        if let Some(bits) = opts.min_strength_bits.filter(|bits| entropy < *bits) {
+            check_write(out.flush());
+            eprintln!(
+                "error: The password has an estimated entropy of {entropy:.1} bits, less than \
+                 the required {bits}"
+            );
+            std::process::exit(1);
+        }
+        if let (Some(log), Some(line), Some(path)) = (&mut audit_log, audit, &opts.audit_log) {
+            writeln!(log, "{line}").unwrap_or_else(|e| {
+                exit_with(GenError::Io(format!(
+                    "Could not write the audit log {}: {e}",
+                    path.display()
+                )))
+            });
+        }
+        let length = gen_pw::graphemes::grapheme_len(&password);
+        let line = match &opts.store_keyring {
+            Some(target) => {
+                keyring::store(target, &password)
+                    .unwrap_or_else(|e| Opts::command().error(clap::ErrorKind::Io, e).exit());
+                format!(
+                    "Stored the password for {} in {}",
+                    target.account, target.service
+                )
+            }
+            None => match (formatted, opts.format) {
+                (Some(line), _) => line,
+                (None, OutputFormat::Plain | OutputFormat::Json) => {
+                    gen_pw::display_groups(opts, &password).unwrap_or(password)
+                }
+                (None, OutputFormat::Env) => format!(
+                    "{}={}",
+                    opts.var.as_deref().unwrap_or_default(),
+                    shell_quote(&password)
+                ),
+            },
+        };
+        if opts.confirm_on_stderr {
+            // The password only goes to the keyring, if anywhere
+            eprintln!("Password generated ({length} chars, {entropy:.0} bits)");
+        } else if opts.sort_output {
+            sorted.push(line);
+        } else {
+            write_line(&mut out, opts, i, &line);
+        }
+        if show_progress && (last_progress.elapsed() >= PROGRESS_INTERVAL || i + 1 == opts.count) {
+            last_progress = Instant::now();
+            eprint!("\r{}/{}", i + 1, opts.count);
+            if i + 1 == opts.count {
+                eprintln!();
+            }
+        }
+    }
+    sorted.sort_unstable();
+    for (i, line) in sorted.iter().enumerate() {
+        write_line(&mut out, opts, i, line);
+    }
+    check_write(out.flush());
+    if opts.measure {
+        eprintln!("Dictionary loading: {loading:.3?}");
+        eprintln!("Generation: {:.3?}", generating.elapsed());
+        eprintln!("Total: {:.3?}", started.elapsed());
+    }
+    if interrupt::interrupted() {
+        // Conventional exit code for termination by SIGINT
+        std::process::exit(130);
+    }
 }
@@ -0,0 +1,238 @@
+//! Password policies loaded with `--policy`.
+//!
+//! A policy file holds one table per policy, using the same TOML subset as
+//! the config file:
+//!
+//! ```toml
+//! [intranet]
+//! min = 12
+//! require = ["number", "symbol"]
+//!
+//! [legacy-mainframe]
+//! max = 16
+//! symbols = "!#$%"
+//! ```
+//!
+//! The generated password has to satisfy all of them, so they are merged into
+//! their intersection: the largest minimum and smallest maximum length, all
+//! required and all excluded classes and only the symbols every policy allows.
+
+use std::{path::Path, str::FromStr};
+
+use crate::{
+    config::{Config, Value},
+    Constraint,
+};
+
+#[derive(Debug, Default)]
+pub struct Policy {
+    pub name: String,
+    pub min: Option<usize>,
+    pub max: Option<usize>,
+    pub require: Vec<Constraint>,
+    pub exclude: Vec<Constraint>,
+    /// The allowed symbols, any symbol if `None`
+    pub symbols: Option<String>,
+    pub max_class_run: Option<usize>,
+}
+
+fn number(name: &str, key: &str, value: &Value) -> Result<usize, String> {
+    match value {
+        Value::Number(n) => n
+            .parse()
+            .map_err(|e| format!("policy '{name}': invalid {key} '{n}': {e}")),
+        _ => Err(format!("policy '{name}': {key} must be a number")),
+    }
+}
+
+fn classes(name: &str, key: &str, value: &Value) -> Result<Vec<Constraint>, String> {
+    let items = match value {
+        Value::Array(items) => items.as_slice(),
+        single => std::slice::from_ref(single),
+    };
+    items
+        .iter()
+        .map(|item| match item {
+            Value::String(s) => Constraint::from_str(s)
+                .map_err(|e| format!("policy '{name}': invalid class in {key}: {e}")),
+            _ => Err(format!("policy '{name}': {key} must hold class names")),
+        })
+        .collect()
+}
+
+impl Policy {
+    fn from_table(name: &str, entries: &[(String, Value)]) -> Result<Self, String> {
+        let mut policy = Policy {
+            name: name.to_string(),
+            ..Policy::default()
+        };
+        for (key, value) in entries {
+            match key.replace('-', "_").as_str() {
+                "min" => policy.min = Some(number(name, key, value)?),
+                "max" => policy.max = Some(number(name, key, value)?),
+                "max_class_run" => policy.max_class_run = Some(number(name, key, value)?),
+                "require" => policy.require = classes(name, key, value)?,
+                "exclude" => policy.exclude = classes(name, key, value)?,
+                "symbols" => match value {
+                    Value::String(s) => policy.symbols = Some(s.clone()),
+                    _ => return Err(format!("policy '{name}': symbols must be a string")),
+                },
+                _ => return Err(format!("policy '{name}': unknown key '{key}'")),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+/// Read all policies from the file at `path`.
+pub fn load(path: &Path) -> Result<Vec<Policy>, String> {
+    let src = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read policy file {}: {e}", path.display()))?;
+    let config =
+        Config::parse(&src).map_err(|e| format!("In policy file {}: {e}", path.display()))?;
+    if let Some((key, _)) = config.global.first() {
+        return Err(format!(
+            "In policy file {}: '{key}' is not part of a [policy] table",
+            path.display()
+        ));
+    }
+    config
+        .tables
+        .iter()
+        .map(|(name, entries)| Policy::from_table(name, entries))
+        .collect()
+}
+
+/// The intersection of several policies.
+#[derive(Debug, Default)]
+pub struct Merged {
+    pub min: Option<usize>,
+    pub max: Option<usize>,
+    pub require: Vec<Constraint>,
+    pub exclude: Vec<Constraint>,
+    pub symbols: Option<String>,
+    pub max_class_run: Option<usize>,
+}
+
+/// Merge `policies` into the strictest combination, or explain why no
+/// password can satisfy all of them.
+pub fn merge(policies: &[Policy]) -> Result<Merged, String> {
+    let mut merged = Merged::default();
+    for policy in policies {
+        merged.min = merged.min.max(policy.min);
+        merged.max = match (merged.max, policy.max) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        merged.max_class_run = match (merged.max_class_run, policy.max_class_run) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        for class in &policy.require {
+            if !merged.require.contains(class) {
+                merged.require.push(*class);
+            }
+        }
+        for class in &policy.exclude {
+            if !merged.exclude.contains(class) {
+                merged.exclude.push(*class);
+            }
+        }
+        if let Some(symbols) = &policy.symbols {
+            merged.symbols = Some(match &merged.symbols {
+                Some(allowed) => allowed.chars().filter(|c| symbols.contains(*c)).collect(),
+                None => symbols.clone(),
+            });
+        }
+    }
+    if let (Some(min), Some(max)) = (merged.min, merged.max) {
+        if min > max {
+            return Err(format!(
+                "The policies require at least {min} but at most {max} characters"
+            ));
+        }
+    }
+    if let Some(class) = merged.require.iter().find(|c| merged.exclude.contains(c)) {
+        let name = clap::ValueEnum::to_possible_value(class)
+            .unwrap()
+            .get_name();
+        return Err(format!(
+            "One policy requires {name} and another one excludes it"
+        ));
+    }
+    if merged.require.contains(&Constraint::Symbol) && merged.symbols.as_deref() == Some("") {
+        return Err("The policies require a symbol but have no symbol in common".to_string());
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::{passwords, required_classes, validate, Opts};
+
+    /// A policy file with `src`, named after the test using it.
+    fn policy_file(name: &str, src: &str) -> String {
+        let path = std::env::temp_dir().join(format!("gen-pw-test-policy-{name}.toml"));
+        std::fs::write(&path, src).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn merges_into_the_intersection() {
+        let path = policy_file(
+            "merge",
+            "[intranet]\nmin = 12\nrequire = [\"number\"]\nsymbols = \"!#$%&\"\n\n\
+             [mainframe]\nmax = 16\nmax-class-run = 3\nrequire = \"symbol\"\nsymbols = \"#%*\"\n",
+        );
+        let policies = load(Path::new(&path)).unwrap();
+        assert_eq!(policies.len(), 2);
+        let merged = merge(&policies).unwrap();
+        assert_eq!((merged.min, merged.max), (Some(12), Some(16)));
+        assert_eq!(merged.require, [Constraint::Number, Constraint::Symbol]);
+        assert_eq!(merged.symbols.as_deref(), Some("#%"));
+        assert_eq!(merged.max_class_run, Some(3));
+
+        let mut opts =
+            Opts::try_parse_from(["gen-pw", "--policy", path.as_str(), "chars"]).unwrap();
+        opts.normalize().unwrap();
+        validate(&opts).unwrap();
+        let required = required_classes(&opts);
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(3);
+        for generated in passwords(&opts, &required, &mut rng).unwrap().take(20) {
+            let password = generated.unwrap().password;
+            assert!((12..=16).contains(&password.len()), "{password}");
+            assert!(password.chars().any(|c| c.is_ascii_digit()), "{password}");
+            assert!(password.contains(['#', '%']), "{password}");
+            assert!(!password.contains(['!', '$', '&', '*']), "{password}");
+        }
+    }
+
+    #[test]
+    fn reports_unsatisfiable_intersections() {
+        let error = |src| {
+            let path = policy_file("conflict", src);
+            merge(&load(Path::new(&path)).unwrap()).unwrap_err()
+        };
+        assert_eq!(
+            error("[a]\nmin = 20\n[b]\nmax = 16\n"),
+            "The policies require at least 20 but at most 16 characters"
+        );
+        assert_eq!(
+            error("[a]\nrequire = \"symbol\"\n[b]\nexclude = \"symbol\"\n"),
+            "One policy requires symbol and another one excludes it"
+        );
+        assert_eq!(
+            error("[a]\nrequire = \"symbol\"\nsymbols = \"!\"\n[b]\nsymbols = \"#\"\n"),
+            "The policies require a symbol but have no symbol in common"
+        );
+        let path = policy_file("unknown-key", "[a]\nlength = 3\n");
+        assert_eq!(
+            load(Path::new(&path)).unwrap_err(),
+            "policy 'a': unknown key 'length'"
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! The `self-test` command, a smoke test of the generator on this system.
+//!
+//! Every case generates a batch of passwords with a fixed set of options and
+//! checks the invariants the generator promises: the length range, the
+//! required classes and the secondary checks. The dictionary based cases only
+//! run if aspell has an English dictionary installed.
+
+use std::panic::{self, AssertUnwindSafe};
+
+use clap::Parser;
+
+use crate::{
+    graphemes::grapheme_len,
+    passes_secondary_checks, passwords, required_classes, validate,
+    words::{self, WordSource},
+    Opts,
+};
+
+/// Passwords generated per case
+const SAMPLES: usize = 200;
+
+const CHARS_CASES: &[&[&str]] = &[
+    &["chars"],
+    &["--min", "5", "--max", "5", "chars"],
+    &["--min", "64", "--max", "64", "chars"],
+    &["--require", "number", "--min", "6", "--max", "6", "chars"],
+    &["--exclude", "symbol", "chars"],
+    &["--letters-only", "chars"],
+    &["--no-letters", "chars"],
+    &["--legacy-gen", "chars"],
+    &["--max-class-run", "2", "chars"],
+    &["--no-leading-zero", "--no-letters", "chars"],
+    &[
+        "--maximize-diversity",
+        "--min",
+        "30",
+        "--max",
+        "40",
+        "chars",
+    ],
+    &["--symbol-probability", "0.5", "chars"],
+];
+
+const DICT_CASES: &[&[&str]] = &[
+    &["dict"],
+    &["--min", "20", "--max", "30", "dict"],
+    &["--max", "40", "dict", "--min-words", "3"],
+    &["dict", "--title-case"],
+    &["mixed"],
+    &["--max", "40", "mixed", "--word-count", "2"],
+    &["compact"],
+];
+
+/// The outcome of one self-test case.
+pub struct Check {
+    /// The options of the case, as on the command line
+    pub name: String,
+    pub result: Result<(), String>,
+}
+
+/// Run all cases, skipping the dictionary based ones if aspell is missing.
+/// The second element reports whether they were skipped.
+pub fn run() -> (Vec<Check>, bool) {
+    let has_aspell = words::Aspell {
+        // The cases pick up the same variable
+        program: std::env::var_os("ASPELL_PATH").map_or("aspell".into(), Into::into),
+        language: "en".to_string(),
+    }
+    .words()
+    .is_ok_and(|words| !words.is_empty());
+    let cases = CHARS_CASES
+        .iter()
+        .chain(DICT_CASES.iter().filter(|_| has_aspell));
+    let checks = cases
+        .map(|args| Check {
+            name: args.join(" "),
+            result: check(args),
+        })
+        .collect();
+    (checks, !has_aspell)
+}
+
+fn check(args: &[&str]) -> Result<(), String> {
+    let mut opts = Opts::try_parse_from(std::iter::once("gen-pw").chain(args.iter().copied()))
+        .map_err(|e| e.to_string())?;
+    opts.normalize().map_err(|e| e.to_string())?;
+    validate(&opts).map_err(|e| e.to_string())?;
+    let required = required_classes(&opts);
+    // A bug in the generator should fail its case, not the whole self-test
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut rng = rand::thread_rng();
+        let compact = matches!(opts.command, crate::Command::Compact { .. });
+        for generated in passwords(&opts, &required, &mut rng)
+            .map_err(|e| e.to_string())?
+            .take(SAMPLES)
+        {
+            let generated = generated.map_err(|e| e.to_string())?;
+            let password = &generated.password;
+            let len = grapheme_len(password);
+            if !compact && (len < opts.min || len > opts.max) {
+                return Err(format!(
+                    "{password} has length {len}, outside of {}..={}",
+                    opts.min, opts.max
+                ));
+            }
+            if let Some(class) = required
+                .iter()
+                .find(|class| !compact && !class.verify(&opts, password))
+            {
+                return Err(format!("{password} lacks a {class:?}"));
+            }
+            if !passes_secondary_checks(&opts, password) {
+                return Err(format!("{password} fails a secondary check"));
+            }
+            if generated.tries as usize > opts.tries {
+                return Err(format!(
+                    "{password} needed {} tries, more than --tries {}",
+                    generated.tries, opts.tries
+                ));
+            }
+        }
+        Ok(())
+    }))
+    .unwrap_or_else(|_| Err("the generator panicked".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn healthy_build_passes() {
+        let (checks, skipped_dict) = run();
+        let expected = CHARS_CASES.len() + if skipped_dict { 0 } else { DICT_CASES.len() };
+        assert_eq!(checks.len(), expected);
+        for check in &checks {
+            assert_eq!(check.result, Ok(()), "{}", check.name);
+        }
+        assert_eq!(checks[1].name, "--min 5 --max 5 chars");
+    }
+
+    #[test]
+    fn invalid_cases_fail() {
+        assert!(check(&["--min", "5", "--max", "3", "chars"]).is_err());
+        assert!(check(&["--no-such-option", "chars"]).is_err());
+    }
+}
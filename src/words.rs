@@ -0,0 +1,435 @@
+//! Sources of dictionary words for the `dict` and `compact` commands.
+//!
+//! Every source produces a plain list of words. Filtering them by length,
+//! apostrophes and the like is left to the caller.
+
+use std::{
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+pub trait WordSource {
+    fn words(&self) -> io::Result<Vec<String>>;
+    /// How to refer to the source in error messages, e.g. "the word list
+    /// words.txt"
+    fn name(&self) -> String;
+}
+
+/// The master dictionary of an installed aspell language, expanded to all
+/// word forms.
+pub struct Aspell {
+    /// The aspell binary, looked up on the `PATH` if it is a plain name
+    pub program: PathBuf,
+    pub language: String,
+}
+
+/// A word list with whitespace separated words, usually one per line. A
+/// leading byte order mark and Windows line endings are ignored.
+pub struct File {
+    pub path: PathBuf,
+    /// Read the list as a frequency list instead, one word and its frequency
+    /// per line, and keep only the words at least this frequent
+    pub min_frequency: Option<u64>,
+}
+
+/// The index files of an installed WordNet database. Multi-word entries
+/// (collocations such as `ice_cream`) are skipped.
+pub struct WordNet {
+    /// The `dict` directory of the database, taken from `$WNSEARCHDIR` or a
+    /// few common install locations if `None`
+    pub dir: Option<PathBuf>,
+}
+
+/// Decode `bytes` as UTF-8, replacing invalid sequences so that the affected
+/// words can be skipped by the caller.
+fn decode(bytes: Vec<u8>, what: &str) -> String {
+    match String::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!(
+                "{what} is not valid UTF-8, skipping the affected words. Check the encoding \
+                 it is installed with."
+            );
+            String::from_utf8_lossy(e.as_bytes()).into_owned()
+        }
+    }
+}
+
+fn split_words(s: &str) -> Vec<String> {
+    s.split_whitespace().map(str::to_string).collect()
+}
+
+impl Aspell {
+    /// Run `aspell dump master`, feeding its output to `stdout`.
+    fn dump(&self, stdout: Stdio) -> io::Result<std::process::Child> {
+        Command::new(&self.program)
+            .args(["-d", &self.language, "dump", "master"])
+            .stdout(stdout)
+            .stderr(Stdio::piped())
+            .spawn()
+    }
+}
+
+/// The error for a failed aspell step, with what aspell printed about it.
+fn step_failed(step: &str, output: &Output) -> io::Error {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    io::Error::other(match stderr.trim() {
+        "" => format!("aspell {step} failed with {}", output.status),
+        message => format!("aspell {step} failed: {message}"),
+    })
+}
+
+impl WordSource for Aspell {
+    /// Runs `aspell dump master | aspell expand`. If only `expand` fails,
+    /// e.g. in builds without it, the words of `dump master` are used without
+    /// their other forms.
+    fn words(&self) -> io::Result<Vec<String>> {
+        let mut dump = self.dump(Stdio::piped())?;
+        // `dump` blocks once the pipe of its errors is full, and `expand` waits
+        // for it, so they have to be read while `expand` runs
+        let mut dump_errors = dump.stderr.take().unwrap();
+        let dump_errors = std::thread::spawn(move || {
+            let mut errors = vec![];
+            dump_errors.read_to_end(&mut errors).map(|_| errors)
+        });
+        let expanded = Command::new(&self.program)
+            .args(["-l", &self.language, "expand"])
+            .stdin(dump.stdout.take().unwrap())
+            .stderr(Stdio::piped())
+            .output()?;
+        let dumped = Output {
+            status: dump.wait()?,
+            stdout: vec![],
+            stderr: dump_errors.join().unwrap_or(Ok(vec![]))?,
+        };
+        if !dumped.status.success() {
+            return Err(step_failed("dump master", &dumped));
+        }
+        let what = format!("The aspell dictionary for '{}'", self.language);
+        if expanded.status.success() {
+            return Ok(split_words(&decode(expanded.stdout, &what)));
+        }
+        warn!(
+            "{}, using the words without their other forms",
+            step_failed("expand", &expanded)
+        );
+        let dumped = self.dump(Stdio::piped())?.wait_with_output()?;
+        if !dumped.status.success() {
+            return Err(step_failed("dump master", &dumped));
+        }
+        // Without `expand` the words keep their affix flags, e.g. `word/S`
+        Ok(decode(dumped.stdout, &what)
+            .split_whitespace()
+            .filter_map(|word| word.split('/').next())
+            .filter(|word| !word.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn name(&self) -> String {
+        format!("the aspell dictionary for '{}'", self.language)
+    }
+}
+
+impl WordSource for File {
+    fn words(&self) -> io::Result<Vec<String>> {
+        let bytes = std::fs::read(&self.path)?;
+        let list = decode(bytes, &format!("The word list {}", self.path.display()));
+        // Editors on Windows like to start files with a byte order mark. The
+        // `\r` of their line endings is whitespace and split off anyway.
+        let list = list.strip_prefix('\u{feff}').unwrap_or(&list);
+        let Some(min_frequency) = self.min_frequency else {
+            return Ok(split_words(list));
+        };
+        let mut words = vec![];
+        for (n, line) in list.lines().enumerate() {
+            let mut fields = line.split_whitespace();
+            let Some(word) = fields.next() else {
+                continue;
+            };
+            let frequency = fields
+                .next()
+                .and_then(|f| f.parse::<u64>().ok())
+                .filter(|_| fields.next().is_none())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line {} is not a word followed by its frequency", n + 1),
+                    )
+                })?;
+            if frequency >= min_frequency {
+                words.push(word.to_string());
+            }
+        }
+        Ok(words)
+    }
+
+    fn name(&self) -> String {
+        format!("the word list {}", self.path.display())
+    }
+}
+
+impl WordNet {
+    fn find_dir(&self) -> io::Result<PathBuf> {
+        let candidates = self
+            .dir
+            .clone()
+            .into_iter()
+            .chain(std::env::var_os("WNSEARCHDIR").map(PathBuf::from))
+            .chain(
+                [
+                    "/usr/share/wordnet",
+                    "/usr/local/share/wordnet",
+                    "/usr/local/WordNet-3.0/dict",
+                ]
+                .map(PathBuf::from),
+            );
+        for dir in candidates {
+            if dir.join("index.noun").is_file() {
+                return Ok(dir);
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no WordNet database found, set WNSEARCHDIR to its dict directory",
+        ))
+    }
+}
+
+fn wordnet_index(path: &Path) -> io::Result<Vec<String>> {
+    let index = decode(
+        std::fs::read(path)?,
+        &format!("The WordNet index {}", path.display()),
+    );
+    Ok(index
+        .lines()
+        // The license header is indented
+        .filter(|line| !line.starts_with(' '))
+        .filter_map(|line| line.split(' ').next())
+        .filter(|word| !word.is_empty() && !word.contains('_'))
+        .map(str::to_string)
+        .collect())
+}
+
+impl WordSource for WordNet {
+    fn words(&self) -> io::Result<Vec<String>> {
+        let dir = self.find_dir()?;
+        let mut words = vec![];
+        for part in ["noun", "verb", "adj", "adv"] {
+            words.extend(wordnet_index(&dir.join(format!("index.{part}")))?);
+        }
+        words.sort_unstable();
+        words.dedup();
+        Ok(words)
+    }
+
+    fn name(&self) -> String {
+        "the WordNet database".to_string()
+    }
+}
+
+/// Accented Latin letters and the ASCII letters they are written as without
+/// the accent
+const ACCENTS: &[(&str, &str)] = &[
+    ("ÀÁÂÃÄÅĀĂĄ", "A"),
+    ("àáâãäåāăą", "a"),
+    ("ÇĆĈĊČ", "C"),
+    ("çćĉċč", "c"),
+    ("ĎĐ", "D"),
+    ("ďđ", "d"),
+    ("ÈÉÊËĒĔĖĘĚ", "E"),
+    ("èéêëēĕėęě", "e"),
+    ("ĜĞĠĢ", "G"),
+    ("ĝğġģ", "g"),
+    ("ĤĦ", "H"),
+    ("ĥħ", "h"),
+    ("ÌÍÎÏĨĪĬĮİ", "I"),
+    ("ìíîïĩīĭįı", "i"),
+    ("Ĵ", "J"),
+    ("ĵ", "j"),
+    ("Ķ", "K"),
+    ("ķ", "k"),
+    ("ĹĻĽĿŁ", "L"),
+    ("ĺļľŀł", "l"),
+    ("ÑŃŅŇ", "N"),
+    ("ñńņň", "n"),
+    ("ÒÓÔÕÖØŌŎŐ", "O"),
+    ("òóôõöøōŏő", "o"),
+    ("ŔŖŘ", "R"),
+    ("ŕŗř", "r"),
+    ("ŚŜŞŠ", "S"),
+    ("śŝşš", "s"),
+    ("ŢŤŦ", "T"),
+    ("ţťŧ", "t"),
+    ("ÙÚÛÜŨŪŬŮŰŲ", "U"),
+    ("ùúûüũūŭůűų", "u"),
+    ("Ŵ", "W"),
+    ("ŵ", "w"),
+    ("ÝŸŶ", "Y"),
+    ("ýÿŷ", "y"),
+    ("ŹŻŽ", "Z"),
+    ("źżž", "z"),
+    ("Æ", "AE"),
+    ("æ", "ae"),
+    ("Œ", "OE"),
+    ("œ", "oe"),
+    ("ß", "ss"),
+    ("Þ", "Th"),
+    ("þ", "th"),
+];
+
+/// Replace the accented Latin letters in `word` with their ASCII base
+/// letters. Other characters are kept as they are.
+pub fn strip_accents(word: &str) -> String {
+    let mut stripped = String::with_capacity(word.len());
+    for c in word.chars() {
+        match ACCENTS.iter().find(|(accented, _)| accented.contains(c)) {
+            Some((_, base)) => stripped.push_str(base),
+            // Combining diacritical marks of decomposed letters
+            None if ('\u{300}'..='\u{36f}').contains(&c) => (),
+            None => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// The warnings logged since [`capture_warnings`] was first called, by
+    /// any test.
+    static WARNINGS: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+    struct Capture;
+
+    impl log::Log for Capture {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Warn
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                WARNINGS.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture_warnings() {
+        if log::set_logger(&Capture).is_ok() {
+            log::set_max_level(log::LevelFilter::Warn);
+        }
+    }
+
+    /// An executable shell script pretending to be aspell, answering
+    /// `dump master` with `dump` and `expand` with `expand`.
+    #[cfg(unix)]
+    fn fake_aspell(name: &str, dump: &str, expand: &str) -> Aspell {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("gen-pw-test-aspell-{name}"));
+        let script =
+            format!("#!/bin/sh\ncase \"$3\" in\ndump) {dump} ;;\nexpand) {expand} ;;\nesac\n");
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        Aspell {
+            program: path,
+            language: "en".to_string(),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn noisy_dump_does_not_block_expand() {
+        let aspell = fake_aspell(
+            "noisy",
+            "head -c 300000 /dev/zero | tr '\\0' x >&2; printf 'apple\\nriver\\n'",
+            "cat",
+        );
+        assert_eq!(aspell.words().unwrap(), ["apple", "river"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn invalid_utf8_is_replaced_with_a_warning() {
+        capture_warnings();
+        // `é` in Latin-1
+        let aspell = fake_aspell("latin1", "printf 'caf\\351\\nriver\\n'", "cat");
+        assert_eq!(aspell.words().unwrap(), ["caf\u{FFFD}", "river"]);
+        assert!(WARNINGS.lock().unwrap().iter().any(
+            |warning| warning.starts_with("The aspell dictionary for 'en' is not valid UTF-8")
+        ));
+    }
+
+    #[test]
+    fn word_lists_from_windows() {
+        let path = std::env::temp_dir().join("gen-pw-test-windows-list.txt");
+        std::fs::write(&path, "\u{feff}apple 12\r\nriver 3\r\n\r\nstone 7\r\n").unwrap();
+        let list = |min_frequency| File {
+            path: path.clone(),
+            min_frequency,
+        };
+        assert_eq!(
+            list(None).words().unwrap(),
+            ["apple", "12", "river", "3", "stone", "7"]
+        );
+        assert_eq!(list(Some(5)).words().unwrap(), ["apple", "stone"]);
+        std::fs::write(&path, "apple 12\r\nriver\r\n").unwrap();
+        let error = list(Some(5)).words().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            error.to_string(),
+            "line 2 is not a word followed by its frequency"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sources_produce_usable_words() {
+        let path = std::env::temp_dir().join("gen-pw-test-source-list.txt");
+        std::fs::write(&path, "apple river\nstone\n").unwrap();
+        let file = File {
+            path,
+            min_frequency: None,
+        };
+        assert_eq!(file.words().unwrap(), ["apple", "river", "stone"]);
+
+        // `expand` adds the other forms of each word. The fakes read their
+        // input, or `dump` could die of SIGPIPE first.
+        let aspell = fake_aspell(
+            "expand",
+            "printf 'horse/S\\n'",
+            "cat >/dev/null; printf 'horse horses\\n'",
+        );
+        assert_eq!(aspell.words().unwrap(), ["horse", "horses"]);
+        // Without `expand` only the affix flags are stripped
+        let aspell = fake_aspell(
+            "no-expand",
+            "printf 'horse/S\\nox\\n'",
+            "cat >/dev/null; exit 1",
+        );
+        assert_eq!(aspell.words().unwrap(), ["horse", "ox"]);
+        let aspell = fake_aspell("no-dump", "echo 'no such dictionary' >&2; exit 1", "cat");
+        assert_eq!(
+            aspell.words().unwrap_err().to_string(),
+            "aspell dump master failed: no such dictionary"
+        );
+
+        let dir = std::env::temp_dir().join("gen-pw-test-wordnet");
+        std::fs::create_dir_all(&dir).unwrap();
+        for (part, index) in [
+            ("noun", "  1 license header\nice_cream n 1\nriver n 1\n"),
+            ("verb", "run v 1\n"),
+            ("adj", "red a 1\n"),
+            ("adv", "run r 1\n"),
+        ] {
+            std::fs::write(dir.join(format!("index.{part}")), index).unwrap();
+        }
+        let wordnet = WordNet { dir: Some(dir) };
+        assert_eq!(wordnet.words().unwrap(), ["red", "river", "run"]);
+    }
+}
@@ -0,0 +1,495 @@
+//! Runs the `gen-pw` binary to check what ends up on stdout and stderr.
+
+use std::{
+    io::Read,
+    process::{Command, Output, Stdio},
+};
+
+fn gen_pw(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_gen-pw"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn confirmation_on_stderr_keeps_stdout_empty() {
+    let printed = gen_pw(&["--seed", "7", "chars"]);
+    assert!(printed.status.success());
+    let password = String::from_utf8(printed.stdout).unwrap();
+    let password = password.trim_end();
+    assert!(!password.is_empty());
+
+    let confirmed = gen_pw(&["--seed", "7", "--confirm-on-stderr", "chars"]);
+    assert!(confirmed.status.success());
+    assert!(confirmed.stdout.is_empty());
+    let stderr = String::from_utf8(confirmed.stderr).unwrap();
+    let length = password.chars().count();
+    assert!(
+        stderr.starts_with(&format!("Password generated ({length} chars, ")),
+        "{stderr}"
+    );
+    assert!(!stderr.contains(password), "{stderr}");
+}
+
+#[test]
+fn closed_stdout_exits_like_sigpipe() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gen-pw"))
+        .args(["--count", "1000000", "chars"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Read a little, like `head -1`, then close the pipe
+    let mut stdout = child.stdout.take().unwrap();
+    let mut first = [0; 16];
+    stdout.read_exact(&mut first).unwrap();
+    drop(stdout);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(141));
+    assert!(
+        output.stderr.is_empty(),
+        "{:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn preview_prints_numbered_candidates() {
+    let output = gen_pw(&["--preview", "5", "--require", "number", "chars"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 5, "{stdout}");
+    for (i, line) in lines.iter().enumerate() {
+        let (number, password) = line.split_once(". ").unwrap();
+        assert_eq!(number, (i + 1).to_string());
+        assert!((10..=20).contains(&password.chars().count()), "{line}");
+        assert!(password.chars().any(|c| c.is_ascii_digit()), "{line}");
+    }
+}
+
+#[cfg(all(unix, feature = "sigint"))]
+#[test]
+fn interrupt_flushes_complete_passwords() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gen-pw"))
+        .args(["--count", "100000000", "--symbols", "!", "chars"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Once output arrives the loop and its handler are running
+    let mut stdout = child.stdout.take().unwrap();
+    let mut first = [0; 1];
+    stdout.read_exact(&mut first).unwrap();
+    let killed = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    let mut rest = vec![];
+    stdout.read_to_end(&mut rest).unwrap();
+    assert_eq!(child.wait().unwrap().code(), Some(130));
+    let output = String::from_utf8([&first[..], &rest].concat()).unwrap();
+    assert!(output.ends_with('\n'), "{:?}", &output[output.len() - 40..]);
+    assert!(output.lines().all(|line| (10..=20).contains(&line.len())));
+}
+
+#[test]
+fn shown_seed_reproduces_the_password() {
+    let shown = gen_pw(&["--show-seed", "chars"]);
+    assert!(shown.status.success());
+    let stderr = String::from_utf8(shown.stderr).unwrap();
+    let seed = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("Seed: "))
+        .unwrap_or_else(|| panic!("{stderr}"));
+    let reproduced = gen_pw(&["--seed", seed, "chars"]);
+    assert!(reproduced.status.success());
+    assert_eq!(reproduced.stdout, shown.stdout);
+}
+
+#[cfg(feature = "kdf")]
+#[test]
+fn revisions_rotate_derived_passwords() {
+    let derive = |revision| {
+        let output = Command::new(env!("CARGO_BIN_EXE_gen-pw"))
+            .args(["derive", "example.com", "--revision", revision])
+            .env("GEN_PW_MASTER", "correct horse")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let zero = derive("0");
+    assert_eq!(zero, derive("0"));
+    assert_ne!(zero, derive("1"));
+}
+
+#[test]
+fn weak_passwords_fail_the_strength_gate() {
+    let weak = gen_pw(&[
+        "--min-strength-bits",
+        "60",
+        "--min",
+        "4",
+        "--max",
+        "4",
+        "--classes",
+        "+number",
+        "chars",
+    ]);
+    assert_eq!(weak.status.code(), Some(1));
+    assert!(weak.stdout.is_empty());
+    let stderr = String::from_utf8(weak.stderr).unwrap();
+    assert!(stderr.contains("less than the required 60"), "{stderr}");
+
+    let strong = gen_pw(&["--min-strength-bits", "60", "--min", "20", "chars"]);
+    assert!(strong.status.success());
+    assert!(!strong.stdout.is_empty());
+}
+
+#[test]
+fn progress_leaves_stdout_alone() {
+    // stderr is a pipe here, not a terminal, so there is no progress either
+    let output = gen_pw(&["--progress", "--count", "50", "chars"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().lines().count(),
+        50
+    );
+    assert!(
+        output.stderr.is_empty(),
+        "{:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Runs under util-linux `script`, which gives the process a terminal.
+#[cfg(target_os = "linux")]
+#[test]
+fn progress_goes_to_the_terminal() {
+    let passwords = std::env::temp_dir().join("gen-pw-test-progress.txt");
+    let command = format!(
+        "{} --progress --count 50 chars > {}",
+        env!("CARGO_BIN_EXE_gen-pw"),
+        passwords.display()
+    );
+    let Ok(output) = Command::new("script")
+        .args(["-qec", &command, "/dev/null"])
+        .output()
+    else {
+        // Not installed
+        return;
+    };
+    assert!(output.status.success());
+    let terminal = String::from_utf8(output.stdout).unwrap();
+    assert!(terminal.contains("50/50"), "{terminal:?}");
+    let written = std::fs::read_to_string(&passwords).unwrap();
+    assert_eq!(written.lines().count(), 50);
+    assert!(written.lines().all(|line| (10..=20).contains(&line.len())));
+}
+
+#[test]
+fn reports_rng_draws_per_password() {
+    let output = gen_pw(&["--sample-rng-report", "--count", "3", "chars"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let draws = stderr
+        .lines()
+        .map(|line| {
+            line.strip_prefix("RNG draws: ")
+                .unwrap()
+                .parse::<u64>()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(draws.len(), 3, "{stderr}");
+    assert!(draws.iter().all(|n| *n > 0), "{stderr}");
+}
+
+/// An executable shell script in the temporary directory.
+#[cfg(unix)]
+fn script(name: &str, body: &str) -> std::path::PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+    let path = std::env::temp_dir().join(format!("gen-pw-test-{name}"));
+    std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+#[cfg(unix)]
+#[test]
+fn uses_the_given_aspell() {
+    let aspell = script(
+        "cli-aspell",
+        "case \"$3\" in\ndump) printf 'zebra\\n' ;;\nexpand) cat ;;\nesac",
+    );
+    let words = gen_pw(&["--aspell-path", aspell.to_str().unwrap(), "words", "3"]);
+    assert!(words.status.success());
+    assert_eq!(words.stdout, b"zebra\nzebra\nzebra\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gen-pw"))
+        .args(["words", "1"])
+        .env("ASPELL_PATH", &aspell)
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, b"zebra\n");
+}
+
+#[test]
+fn compare_prefers_the_stronger_config() {
+    let dir = std::env::temp_dir();
+    let [weak, strong] = [("weak", 8), ("strong", 24)].map(|(name, length)| {
+        let path = dir.join(format!("gen-pw-test-compare-{name}.toml"));
+        std::fs::write(
+            &path,
+            format!("command = \"chars\"\nmin = {length}\nmax = {length}\n"),
+        )
+        .unwrap();
+        path.to_str().unwrap().to_string()
+    });
+    let output = gen_pw(&["compare", &weak, &strong]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let row = |name: &str| {
+        let line = stdout.lines().find(|line| line.starts_with(name)).unwrap();
+        line[name.len()..]
+            .split_whitespace()
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+    let entropy = row("mean entropy (bits)")
+        .iter()
+        .map(|bits| bits.parse::<f64>().unwrap())
+        .collect::<Vec<_>>();
+    assert!(entropy[1] > entropy[0], "{stdout}");
+    assert_eq!(row("length"), ["8-8", "24-24"]);
+}
+
+#[test]
+fn no_newline_after_the_last_password() {
+    let single = gen_pw(&["--no-newline", "chars"]);
+    assert!(single.status.success());
+    assert!(!single.stdout.is_empty());
+    assert!(!single.stdout.contains(&b'\n'));
+
+    let batch = gen_pw(&["--no-newline", "--count", "3", "chars"]);
+    let stdout = String::from_utf8(batch.stdout).unwrap();
+    assert!(!stdout.ends_with('\n'));
+    assert_eq!(stdout.split('\n').count(), 3, "{stdout:?}");
+}
+
+#[cfg(unix)]
+#[test]
+fn words_prints_the_requested_number() {
+    let aspell = script(
+        "cli-aspell-words",
+        "case \"$3\" in\ndump) printf \"apple\\nriver\\ndon't\\nextraordinarily\\n\" ;;\nexpand) cat ;;\nesac",
+    );
+    let aspell = aspell.to_str().unwrap();
+    let output = gen_pw(&["--aspell-path", aspell, "--max", "10", "words", "25"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let words = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(words.len(), 25);
+    // The filters of dict still apply
+    assert!(
+        words.iter().all(|word| ["apple", "river"].contains(word)),
+        "{stdout}"
+    );
+    let default = gen_pw(&["--aspell-path", aspell, "words"]);
+    assert_eq!(
+        String::from_utf8(default.stdout).unwrap().lines().count(),
+        10
+    );
+}
+
+/// A duration in the `Debug` format of `Duration`, e.g. `1.250ms`.
+fn parse_duration(s: &str) -> std::time::Duration {
+    let split = s.find(|c: char| c.is_alphabetic()).unwrap();
+    let (number, unit) = s.split_at(split);
+    let number = number.parse::<f64>().unwrap();
+    let seconds = match unit {
+        "s" => number,
+        "ms" => number / 1e3,
+        "µs" => number / 1e6,
+        "ns" => number / 1e9,
+        _ => panic!("unknown unit in {s}"),
+    };
+    std::time::Duration::from_secs_f64(seconds)
+}
+
+#[test]
+fn measures_the_phases() {
+    let output = gen_pw(&["--measure", "--count", "200", "chars"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().lines().count(),
+        200
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let phase = |name: &str| {
+        let line = stderr
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .unwrap_or_else(|| panic!("no {name} in {stderr}"));
+        parse_duration(line)
+    };
+    let loading = phase("Dictionary loading: ");
+    let generation = phase("Generation: ");
+    let total = phase("Total: ");
+    // Each is rounded to three decimals
+    let slack = std::time::Duration::from_millis(1);
+    assert!(total + slack >= loading + generation, "{stderr}");
+}
+
+#[test]
+fn pools_report_goes_to_stderr() {
+    let output = gen_pw(&[
+        "--per-class-pools-report",
+        "--exclude",
+        "symbol",
+        "--number-chars",
+        "123",
+        "chars",
+    ]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("number             123 (3 characters)"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("symbol             (not picked)"),
+        "{stderr}"
+    );
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 1);
+}
+
+#[test]
+fn sorted_output() {
+    let output = gen_pw(&["--sort-output", "--count", "40", "chars"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 40);
+    assert!(lines.windows(2).all(|pair| pair[0] <= pair[1]), "{stdout}");
+}
+
+#[test]
+fn version_is_only_the_version() {
+    for args in [&["--version"][..], &["--debug", "--version"]] {
+        let output = gen_pw(args);
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!("gen-pw {}\n", env!("CARGO_PKG_VERSION"))
+        );
+        assert!(output.stderr.is_empty());
+    }
+}
+
+#[test]
+fn banner_off_silences_the_debug_banner() {
+    let banner = gen_pw(&["--debug", "chars"]);
+    assert!(String::from_utf8(banner.stderr)
+        .unwrap()
+        .contains("Running with debug"));
+    let quiet = gen_pw(&["--debug", "--banner-off", "chars"]);
+    assert!(!String::from_utf8(quiet.stderr)
+        .unwrap()
+        .contains("Running with debug"));
+}
+
+#[test]
+fn audit_log_gets_a_line_per_password() {
+    let log = std::env::temp_dir().join("gen-pw-test-audit.log");
+    let _ = std::fs::remove_file(&log);
+    let output = gen_pw(&[
+        "--audit-log",
+        log.to_str().unwrap(),
+        "--count",
+        "3",
+        "chars",
+    ]);
+    assert!(output.status.success());
+    let audit = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(audit.lines().count(), 3);
+    for password in String::from_utf8(output.stdout).unwrap().lines() {
+        assert!(!audit.contains(password), "{audit}");
+    }
+    assert!(audit
+        .lines()
+        .all(|line| line.contains("\"command\":\"chars\"")));
+}
+
+#[test]
+fn token_selects_a_candidate_again() {
+    let offered = gen_pw(&["--choose-from", "4", "chars"]);
+    assert!(offered.status.success());
+    let stderr = String::from_utf8(offered.stderr).unwrap();
+    let token = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("Token: "))
+        .unwrap_or_else(|| panic!("{stderr}"));
+    let stdout = String::from_utf8(offered.stdout).unwrap();
+    let candidates = stdout
+        .lines()
+        .map(|line| line.split_once(". ").unwrap().1)
+        .collect::<Vec<_>>();
+    assert_eq!(candidates.len(), 4);
+    for (k, candidate) in candidates.iter().enumerate() {
+        let k = (k + 1).to_string();
+        let chosen = gen_pw(&["--choose", &k, "--token", token, "chars"]);
+        assert!(chosen.status.success());
+        assert_eq!(
+            String::from_utf8(chosen.stdout).unwrap(),
+            format!("{candidate}\n")
+        );
+    }
+}
+
+#[test]
+fn auto_relax_reports_the_dropped_check() {
+    let strict = [
+        "--tries",
+        "50",
+        "--max-consecutive-consonants",
+        "0",
+        "--require",
+        "lower-case-letter",
+        "chars",
+    ];
+    let failed = gen_pw(&strict);
+    assert_eq!(failed.status.code(), Some(1));
+
+    let relaxed = gen_pw(&[&["--auto-relax"][..], &strict].concat());
+    assert!(relaxed.status.success());
+    let stderr = String::from_utf8(relaxed.stderr).unwrap();
+    assert!(
+        stderr.contains("dropping --max-consecutive-vowels and --max-consecutive-consonants"),
+        "{stderr}"
+    );
+    let password = String::from_utf8(relaxed.stdout).unwrap();
+    // Only the soft check is dropped, the classes stay the same
+    assert!(
+        password.trim_end().chars().all(|c| c.is_ascii_lowercase()),
+        "{password}"
+    );
+}
+
+#[test]
+fn dry_parse_only_validates() {
+    let valid = gen_pw(&["--dry-parse", "chars"]);
+    assert!(valid.status.success());
+    assert_eq!(valid.stdout, b"OK\n");
+
+    let invalid = gen_pw(&["--dry-parse", "--min", "30", "--max", "20", "chars"]);
+    assert_eq!(invalid.status.code(), Some(2));
+    assert!(invalid.stdout.is_empty());
+    let stderr = String::from_utf8(invalid.stderr).unwrap();
+    assert!(
+        stderr.starts_with("error: --min 30 is larger than --max 20"),
+        "{stderr}"
+    );
+}